@@ -0,0 +1,48 @@
+/**
+ * request_id
+ * Compares allocating request IDs with `SeqCst` against the `Relaxed` ordering that
+ * `Omegga` uses, from one thread and from several at once.
+ *
+ * Run with `cargo bench --bench request_id`.
+ */
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const IDS_PER_THREAD: usize = 1_000_000;
+const THREADS: usize = 4;
+
+fn allocate(counter: &AtomicI32, ordering: Ordering) {
+    for _ in 0..IDS_PER_THREAD {
+        black_box(counter.fetch_add(1, ordering));
+    }
+}
+
+fn time(threads: usize, ordering: Ordering) -> Duration {
+    let counter = Arc::new(AtomicI32::new(0));
+    let start = Instant::now();
+    let handles = (0..threads)
+        .map(|_| {
+            let counter = Arc::clone(&counter);
+            thread::spawn(move || allocate(&counter, ordering))
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    for threads in [1, THREADS] {
+        println!("{} IDs on {} thread(s):", IDS_PER_THREAD * threads, threads);
+        println!("  SeqCst:  {:?}", time(threads, Ordering::SeqCst));
+        println!("  Relaxed: {:?}", time(threads, Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,50 @@
+/**
+ * write
+ * Compares writing 10k small notifications with a `println!` each, as `Omegga::write`
+ * used to, against its single locked and flushed write per message, and against
+ * queueing them all with `buffer_write` and flushing once.
+ *
+ * Run with `cargo bench --bench write > /dev/null`, as the timings go to stderr.
+ */
+use std::time::{Duration, Instant};
+
+use omegga::{rpc, Omegga};
+use serde_json::json;
+
+const NOTIFICATIONS: usize = 10_000;
+
+fn notification() -> rpc::Message {
+    rpc::Message::notification("broadcast".into(), Some(json!(["hello"])))
+}
+
+fn time(run: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    run();
+    start.elapsed()
+}
+
+fn main() {
+    let omegga = Omegga::new();
+
+    let println = time(|| {
+        for _ in 0..NOTIFICATIONS {
+            println!("{}", serde_json::to_string(&notification()).unwrap());
+        }
+    });
+    let locked = time(|| {
+        for _ in 0..NOTIFICATIONS {
+            omegga.write(notification());
+        }
+    });
+    let buffered = time(|| {
+        for _ in 0..NOTIFICATIONS {
+            omegga.buffer_write(notification()).unwrap();
+        }
+        omegga.flush().unwrap();
+    });
+
+    eprintln!("{} notifications:", NOTIFICATIONS);
+    eprintln!("  println! per message:   {:?}", println);
+    eprintln!("  locked and flushed:     {:?}", locked);
+    eprintln!("  buffered, flushed once: {:?}", buffered);
+}
@@ -18,10 +18,10 @@ async fn main() {
     while let Some(event) = rx.recv().await {
         match event {
             Event::Init { id, .. } => {
-                omegga.register_commands(id, &[]);
+                omegga.respond_init(id, Vec::<String>::new());
                 omegga.log("Hello from omegga-rs!");
             }
-            Event::Stop { id, .. } => omegga.write_response(id, None, None),
+            Event::Stop { id, .. } => omegga.respond_stop(id),
 
             Event::ChatCommand { command, args, .. } => match command.as_str() {
                 "new-cmd" => {
@@ -29,15 +29,15 @@ async fn main() {
                     let name = args.next().unwrap();
                     let content = args.collect::<Vec<_>>().join(" ");
 
-                    if let None = commands.insert(name.clone(), content) {
+                    if commands.insert(name.clone(), content).is_none() {
                         omegga.broadcast(format!("OK, created the custom command {}.", name));
                     } else {
-                        omegga.broadcast(format!("OK, overwrote that existing custom command."));
+                        omegga.broadcast("OK, overwrote that existing custom command.");
                     }
                 }
                 "del-cmd" => {
                     let name = args.into_iter().next().unwrap();
-                    if let Some(_) = commands.remove(&name) {
+                    if commands.remove(&name).is_some() {
                         omegga.broadcast(format!("OK, removed the custom command {}.", name));
                     } else {
                         omegga.broadcast("That custom command didn't exist.");
@@ -0,0 +1,26 @@
+/**
+ * handlers
+ * This sample plugin registers handlers instead of matching on events itself.
+ * It greets players when they join, and replies to !ping or /ping with Pong!
+ */
+use omegga::Omegga;
+
+#[tokio::main]
+async fn main() {
+    let omegga = Omegga::new();
+
+    let o = omegga.clone();
+    omegga.on_join(move |player| {
+        let o = o.clone();
+        async move { o.broadcast(format!("Welcome, {}!", player.name)) }
+    });
+
+    let o = omegga.clone();
+    omegga.on_command("ping", move |call| {
+        let o = o.clone();
+        async move { o.whisper(call.player, "Pong!") }
+    });
+
+    // Responds to init with the `ping` command, and returns once Omegga stops the plugin
+    omegga.run().await;
+}
@@ -8,19 +8,17 @@ async fn main() {
     while let Some(event) = events.recv().await {
         match event {
             // Register our commands on init...
-            Event::Init { id, .. } => omegga.register_commands(id, &["ping"]),
+            Event::Init { id, .. } => omegga.respond_init(id, ["ping"]),
 
             // Send a blank response when we're told to stop...
-            Event::Stop { id, .. } => omegga.write_response(id, None, None),
+            Event::Stop { id, .. } => omegga.respond_stop(id),
 
-            // Listen to commands sent to the plugin...
+            // When the plugin receives the `ping` command, send `Pong!`
             Event::Command {
                 player, command, ..
-            } => match command.as_str() {
-                // When the command matches `ping`, send `Pong!`
-                "ping" => omegga.whisper(player, "Pong!"),
-                _ => (),
-            },
+            } if command == "ping" => {
+                let _ = omegga.whisper(player, "Pong!");
+            }
 
             _ => (),
         }
@@ -0,0 +1,315 @@
+use std::{collections::BTreeMap, future::Future};
+
+use crate::{
+    events::Event,
+    handlers::{boxed, Handler, HandlerResult},
+    Omegga,
+};
+
+/// A command invocation, passed to command handlers.
+#[derive(Debug, Clone)]
+pub struct CommandCall {
+    pub player: String,
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+/// Builds a [`CommandRouter`] from command names and handlers, each an `async fn(CommandCall)`
+/// or a closure returning a future, as for [`CommandRouter::on`]:
+/// `command_router! { "!give" => give, "!ping" => ping }`,
+/// or `command_router!(prefix = "/"; "give" => give)` for a custom prefix.
+#[macro_export]
+macro_rules! command_router {
+    (prefix = $prefix:expr; $($name:expr => $handler:expr),* $(,)?) => {{
+        let mut router = $crate::commands::CommandRouter::with_prefix($prefix);
+        $(router.on($name, $handler);)*
+        router
+    }};
+    ($($name:expr => $handler:expr),* $(,)?) => {
+        $crate::command_router!(prefix = "!"; $($name => $handler),*)
+    };
+}
+
+/// Dispatches commands to async handlers registered by name.
+///
+/// Handlers return either `()` or a `Result`, as with [`Omegga::on_command`](Omegga::on_command),
+/// and errors they return are logged to the Omegga console with [`Omegga::error`](Omegga::error).
+///
+/// Omegga already strips the prefix from `cmd:` and `chatcmd:` events, so the
+/// prefix (`!` by default) is only used to normalize registered names and to
+/// parse raw chat lines passed to [`dispatch_line`](CommandRouter::dispatch_line).
+pub struct CommandRouter {
+    prefix: String,
+    handlers: BTreeMap<String, Handler<CommandCall>>,
+    fallback: Option<Handler<CommandCall>>,
+    quoted: bool,
+}
+
+impl CommandRouter {
+    /// Create a new router with the default `!` prefix.
+    pub fn new() -> Self {
+        Self::with_prefix("!")
+    }
+
+    /// Create a new router with a custom prefix.
+    pub fn with_prefix(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            handlers: BTreeMap::new(),
+            fallback: None,
+            quoted: false,
+        }
+    }
+
+    /// Register a handler for a command. The name may include the prefix (`!give` or `give`).
+    pub fn on<F, Fut>(&mut self, name: &str, handler: F) -> &mut Self
+    where
+        F: Fn(CommandCall) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        let name = name.strip_prefix(self.prefix.as_str()).unwrap_or(name);
+        self.handlers.insert(name.to_string(), boxed(handler));
+        self
+    }
+
+    /// Register a catch-all handler for commands with no registered handler.
+    pub fn fallback<F, Fut>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(CommandCall) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        self.fallback = Some(boxed(handler));
+        self
+    }
+
+    /// Set whether arguments are split with [`split_quoted`] instead of on whitespace,
+    /// so `!msg "hello world" target` passes `["hello world", "target"]`. Off by default.
+    pub fn quoted_args(&mut self, enabled: bool) -> &mut Self {
+        self.quoted = enabled;
+        self
+    }
+
+    /// The names of the registered commands, without the prefix, in sorted order.
+    pub fn command_names(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(String::as_str)
+    }
+
+    /// Dispatch an `Event::Command` or `Event::ChatCommand` to its handler, logging any
+    /// error it returns to `omegga`'s console. Returns whether a handler (including the
+    /// fallback) was invoked.
+    pub async fn dispatch(&self, omegga: &Omegga, event: &Event) -> bool {
+        match event {
+            Event::Command {
+                player,
+                command,
+                args,
+            }
+            | Event::ChatCommand {
+                player,
+                command,
+                args,
+            } => {
+                // Omegga splits arguments on whitespace, so rejoin them to respect quotes
+                let args = if self.quoted {
+                    split_quoted(&args.join(" "))
+                } else {
+                    args.clone()
+                };
+
+                self.call(
+                    omegga,
+                    CommandCall {
+                        player: player.clone(),
+                        command: command.clone(),
+                        args,
+                    },
+                )
+                .await
+            }
+            _ => false,
+        }
+    }
+
+    /// Parse a raw chat line like `!give sword 2` and dispatch it like
+    /// [`dispatch`](CommandRouter::dispatch). Returns whether a handler (including the
+    /// fallback) was invoked.
+    pub async fn dispatch_line(
+        &self,
+        omegga: &Omegga,
+        player: impl Into<String>,
+        line: &str,
+    ) -> bool {
+        let line = match line.strip_prefix(self.prefix.as_str()) {
+            Some(line) => line,
+            None => return false,
+        };
+
+        let mut tokens = if self.quoted {
+            split_quoted(line)
+        } else {
+            line.split_whitespace().map(String::from).collect()
+        }
+        .into_iter();
+        let command = match tokens.next() {
+            Some(command) => command,
+            None => return false,
+        };
+
+        self.call(
+            omegga,
+            CommandCall {
+                player: player.into(),
+                command,
+                args: tokens.collect(),
+            },
+        )
+        .await
+    }
+
+    async fn call(&self, omegga: &Omegga, call: CommandCall) -> bool {
+        match self
+            .handlers
+            .get(call.command.as_str())
+            .or(self.fallback.as_ref())
+        {
+            Some(handler) => {
+                if let Some(error) = handler(call).await {
+                    omegga.error(error);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Split a line into arguments on whitespace, keeping double-quoted text together:
+/// `"hello world" target` becomes `["hello world", "target"]`.
+///
+/// A backslash escapes the character after it, so `\"` is a literal quote. An
+/// unclosed quote runs to the end of the line.
+pub fn split_quoted(line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    // whether there's an argument in progress, which may be an empty `""`
+    let mut in_arg = false;
+    let mut in_quotes = false;
+
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                in_arg = true;
+                if let Some(c) = chars.next() {
+                    arg.push(c);
+                }
+            }
+            '"' => {
+                in_arg = true;
+                in_quotes = !in_quotes;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            c => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+
+impl Default for CommandRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockOmegga, rpc};
+
+    fn command(name: &str) -> Event {
+        Event::Command {
+            player: "x".into(),
+            command: name.into(),
+            args: vec!["a".into()],
+        }
+    }
+
+    fn logged_error(message: &rpc::Message) -> bool {
+        matches!(message, rpc::Message::Notification { method, params, .. }
+            if method == "error" && params.as_ref().and_then(|p| p.as_str()) == Some("failed a"))
+    }
+
+    async fn fail(call: CommandCall) -> Result<(), String> {
+        Err(format!("failed {}", call.args[0]))
+    }
+
+    #[tokio::test]
+    async fn router_logs_handler_errors() {
+        let mock = MockOmegga::new();
+        let router = command_router! { "!fail" => fail };
+
+        assert!(router.dispatch(&mock.omegga(), &command("fail")).await);
+        assert!(!router.dispatch(&mock.omegga(), &command("other")).await);
+        mock.wait_for_sent(logged_error).await;
+    }
+
+    #[tokio::test]
+    async fn on_command_takes_the_same_handlers() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        omegga.on_command("fail", fail);
+
+        let run = mock.run();
+        tokio::pin!(run);
+        let sent = async {
+            mock.notify("cmd:fail", Some(serde_json::json!(["x", "a"])))
+                .await
+                .unwrap();
+            mock.wait_for_sent(logged_error).await
+        };
+        tokio::select! {
+            _ = &mut run => panic!("run returned early"),
+            _ = sent => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn init_registers_router_commands_in_order() {
+        let mock = MockOmegga::new();
+        let router = command_router! {
+            "!zap" => fail,
+            "ask" => fail,
+            "!ping" => fail,
+        };
+        assert_eq!(
+            router.command_names().collect::<Vec<_>>(),
+            ["ask", "ping", "zap"]
+        );
+
+        mock.omegga()
+            .respond_init_from_router(rpc::RequestId::Int(7), &router);
+        let response = mock.wait_for_sent(|_| true).await;
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "result": {"registeredCommands": ["ask", "ping", "zap"]}
+            })
+        );
+    }
+}
@@ -1,6 +1,13 @@
+use std::collections::HashMap;
+
+use serde::de::{DeserializeOwned, Error as _};
 use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
 
-use crate::{resources::Player, rpc::RequestId};
+use crate::{
+    resources::Player,
+    rpc::{RequestId, Response},
+};
 
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -33,7 +40,12 @@ pub enum Event {
     },
     Version(Value),
     Unauthorized,
+    /// A player joined the server.
+    ///
+    /// Omegga doesn't say whether this is the player's first join, so plugins welcoming
+    /// new players need to remember who they've seen, such as in the [store](crate::Omegga::store).
     Join(Player),
+    /// A player left the server.
     Leave(Player),
     Command {
         player: String,
@@ -56,7 +68,32 @@ pub enum Event {
         player: Player,
         args: Vec<String>,
     },
+    /// Omegga is about to restart the server automatically, with its autorestart config.
     Autorestart(Value),
+    /// The listener stopped because Omegga closed its input or reading from it
+    /// failed. This is the last event before the channel closes, and isn't sent
+    /// for a [`shutdown`](crate::Omegga::shutdown).
+    Disconnected,
+    /// A response with no request waiting for it, such as one that arrived after its
+    /// request timed out. Only sent when enabled with
+    /// [`OmeggaBuilder::forward_orphans`](crate::OmeggaBuilder::forward_orphans).
+    OrphanResponse(Response),
+    /// A request or notification this crate doesn't recognize.
+    /// `id` is present for requests, which still expect a response.
+    Unknown {
+        id: Option<RequestId>,
+        method: String,
+        params: Option<Value>,
+    },
+}
+
+/// The contents of Omegga's `init` request, from [`Omegga::await_init`](crate::Omegga::await_init).
+#[derive(Debug, Clone)]
+pub struct InitData {
+    /// The ID to respond to, with [`Omegga::respond_init`](crate::Omegga::respond_init).
+    pub id: RequestId,
+    /// The plugin's config from the web UI.
+    pub config: Value,
 }
 
 /// A player from interact.
@@ -64,18 +101,393 @@ pub enum Event {
 pub struct PlayerInteract {
     pub name: String,
     pub id: String,
+    #[serde(default)]
     pub controller: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
 }
 
+/// A player clicking an interactable brick.
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct BrickInteraction {
+    #[serde(default)]
     pub brick_asset: String,
+    #[serde(default)]
     pub brick_name: String,
+    #[serde(default)]
     pub brick_size: Vec<u64>,
     pub player: PlayerInteract,
     pub position: (f64, f64, f64),
+    /// The brick's console message parsed as JSON, when `json` is set.
+    #[serde(default)]
     pub data: Option<Value>,
+    /// Whether the message looked like JSON but failed to parse.
+    #[serde(default)]
     pub error: bool,
+    /// Whether the message parsed as JSON into `data`.
+    #[serde(default)]
     pub json: bool,
-    pub message: String
+    /// The brick's console message as it was set, for plugins parsing their own formats.
+    #[serde(default)]
+    pub message: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+impl BrickInteraction {
+    /// Deserialize the interaction's JSON `data`, or `None` if it has none or it doesn't match.
+    pub fn data_as<T: DeserializeOwned>(&self) -> Option<T> {
+        serde_json::from_value(self.data.clone()?).ok()
+    }
+}
+
+/// A chat message, with its markup stripped for matching against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedChat {
+    /// The name of the player who sent the message.
+    pub player: String,
+    /// The message as it was received, markup and all.
+    pub raw: String,
+    /// The message with its markup stripped, as the players see it.
+    pub text: String,
+}
+
+impl ParsedChat {
+    /// Parse a chat message sent by `player`.
+    pub fn new(player: impl Into<String>, message: impl Into<String>) -> Self {
+        let raw = message.into();
+        ParsedChat {
+            player: player.into(),
+            text: strip_chat_markup(&raw),
+            raw,
+        }
+    }
+}
+
+/// Strip Brickadia chat markup from a message, removing tags like `<color="ff0000">`
+/// and `</>` and unescaping the entities produced by [`resources::escape`](crate::resources::escape).
+///
+/// Entities are unescaped after tags are removed, so escaped text like `&lt;b&gt;`
+/// comes out as a literal `<b>`.
+pub fn strip_chat_markup(message: &str) -> String {
+    let mut text = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        match rest[start..].find('>') {
+            Some(end) => rest = &rest[start + end + 1..],
+            // an unclosed tag isn't markup
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        }
+    }
+    text.push_str(rest);
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&scl;", ";")
+        .replace("&amp;", "&")
+}
+
+/// A stream of events from [`Omegga::subscribe`](crate::Omegga::subscribe), optionally
+/// filtered by name.
+pub struct Subscription {
+    rx: broadcast::Receiver<Event>,
+    name: Option<String>,
+}
+
+impl Subscription {
+    pub(crate) fn new(rx: broadcast::Receiver<Event>, name: Option<String>) -> Self {
+        Subscription { rx, name }
+    }
+
+    /// Receive the next matching event, or `None` once every `Omegga` is dropped.
+    ///
+    /// A subscription that falls too far behind skips the oldest events it missed.
+    pub async fn recv(&mut self) -> Option<Event> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => match &self.name {
+                    Some(name) if event.name() != name => continue,
+                    _ => return Some(event),
+                },
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Event {
+    /// The name of the event, after the Omegga method it was parsed from: `"join"`,
+    /// `"cmd"`, `"chatcmd"`, `"event"` and so on, or the method itself for an unknown event.
+    pub fn name(&self) -> &str {
+        match self {
+            Event::Init { .. } => "init",
+            Event::Stop { .. } => "stop",
+            Event::Bootstrap { .. } => "bootstrap",
+            Event::PluginPlayersRaw { .. } => "plugin:players:raw",
+            Event::PluginEmit { .. } => "plugin:emit",
+            Event::Line(_) => "line",
+            Event::Start { .. } => "start",
+            Event::Host { .. } => "host",
+            Event::Version(_) => "version",
+            Event::Unauthorized => "unauthorized",
+            Event::Join(_) => "join",
+            Event::Leave(_) => "leave",
+            Event::Command { .. } => "cmd",
+            Event::ChatCommand { .. } => "chatcmd",
+            Event::Chat { .. } => "chat",
+            Event::MapChange(_) => "mapchange",
+            Event::Interact(_) => "interact",
+            Event::Event { .. } => "event",
+            Event::Autorestart(_) => "autorestart",
+            Event::Disconnected => "disconnected",
+            Event::OrphanResponse(_) => "orphan",
+            Event::Unknown { method, .. } => method,
+        }
+    }
+
+    /// The ID of the request the event came from, which Omegga expects a response to,
+    /// or `None` for an event from a notification.
+    pub fn request_id(&self) -> Option<&RequestId> {
+        match self {
+            Event::Init { id, .. } | Event::Stop { id } | Event::PluginEmit { id, .. } => Some(id),
+            Event::Unknown { id, .. } => id.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Parse a chat event into a [`ParsedChat`], or `None` for any other event.
+    pub fn parsed_chat(&self) -> Option<ParsedChat> {
+        match self {
+            Event::Chat { player, message } => Some(ParsedChat::new(player, message)),
+            _ => None,
+        }
+    }
+
+    /// Parse a request from Omegga into an event.
+    pub(crate) fn from_request(id: RequestId, method: &str, params: Option<Value>) -> Option<Self> {
+        match method {
+            "init" => Some(Event::Init {
+                id,
+                config: params.unwrap_or(Value::Null),
+            }),
+            "stop" => Some(Event::Stop { id }),
+            "plugin:emit" => match params {
+                Some(Value::Array(mut v))
+                    if matches!(v.as_slice(), [Value::String(_), Value::String(_), ..]) =>
+                {
+                    let args = v.split_off(2);
+                    Some(Event::PluginEmit {
+                        id,
+                        event: v[0].as_str().unwrap().to_string(),
+                        from: v[1].as_str().unwrap().to_string(),
+                        args,
+                    })
+                }
+                // keep a malformed emit as an unknown request, so its id can still be answered
+                _ => Some(Event::Unknown {
+                    id: Some(id),
+                    method: method.to_string(),
+                    params,
+                }),
+            },
+            _ => Some(Event::Unknown {
+                id: Some(id),
+                method: method.to_string(),
+                params,
+            }),
+        }
+    }
+
+    /// Parse a notification from Omegga into an event, failing if its params are malformed.
+    pub(crate) fn from_notification(
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<Option<Self>, serde_json::Error> {
+        Ok(match method {
+            "bootstrap" => Some(Event::Bootstrap {
+                omegga: params.unwrap_or(Value::Null),
+            }),
+            "plugin:players:raw" => Some(Event::PluginPlayersRaw {
+                players: serde_json::from_value(params.unwrap_or_default()).unwrap_or_default(),
+            }),
+            "line" => Some(Event::Line(first_param(params)?)),
+            "start" => {
+                #[derive(serde::Deserialize)]
+                struct MapParams {
+                    map: String,
+                }
+
+                Some(Event::Start {
+                    map: first_param::<MapParams>(params)?.map,
+                })
+            }
+            "host" => {
+                #[derive(serde::Deserialize)]
+                struct HostParams {
+                    name: String,
+                    id: String,
+                }
+
+                let host = first_param::<HostParams>(params)?;
+
+                Some(Event::Host {
+                    name: host.name,
+                    id: host.id,
+                })
+            }
+            "version" => Some(Event::Version(params.unwrap_or(Value::Null))),
+            "unauthorized" => Some(Event::Unauthorized),
+            "join" => Some(Event::Join(first_param(params)?)),
+            "leave" => Some(Event::Leave(first_param(params)?)),
+            e if e.starts_with("cmd:") => {
+                let c = &e[4..];
+                let mut params = params_as::<Vec<String>>(params)?.into_iter();
+
+                Some(Event::Command {
+                    player: params.next().ok_or_else(|| missing("player"))?,
+                    command: c.to_string(),
+                    args: params.collect(),
+                })
+            }
+            e if e.starts_with("chatcmd:") => {
+                let c = &e[8..];
+                let mut params = params_as::<Vec<String>>(params)?.into_iter();
+
+                Some(Event::ChatCommand {
+                    player: params.next().ok_or_else(|| missing("player"))?,
+                    command: c.to_string(),
+                    args: params.collect(),
+                })
+            }
+            "chat" => {
+                let mut params = params_as::<Vec<String>>(params)?.into_iter();
+
+                Some(Event::Chat {
+                    player: params.next().ok_or_else(|| missing("player"))?,
+                    message: params.next().ok_or_else(|| missing("message"))?,
+                })
+            }
+            "mapchange" => {
+                #[derive(serde::Deserialize)]
+                struct MapParams {
+                    map: String,
+                }
+
+                Some(Event::MapChange(first_param::<MapParams>(params)?.map))
+            }
+            "interact" => Some(Event::Interact(first_param(params)?)),
+            e if e.starts_with("event:") => {
+                let e = &e[6..];
+                let mut params = params_as::<Vec<Value>>(params)?.into_iter();
+                let player = params.next().ok_or_else(|| missing("player"))?;
+
+                Some(Event::Event {
+                    name: String::from(e),
+                    player: serde_json::from_value::<Player>(player)?,
+                    args: params
+                        .map(serde_json::from_value::<String>)
+                        .collect::<Result<_, _>>()?,
+                })
+            }
+            "autorestart" => Some(Event::Autorestart(params.unwrap_or_default())),
+            _ => Some(Event::Unknown {
+                id: None,
+                method: method.to_string(),
+                params,
+            }),
+        })
+    }
+}
+
+/// Deserialize a notification's params, reading missing params as `null`.
+fn params_as<T: DeserializeOwned>(params: Option<Value>) -> Result<T, serde_json::Error> {
+    serde_json::from_value(params.unwrap_or(Value::Null))
+}
+
+/// Deserialize the first of a notification's positional params.
+fn first_param<T: DeserializeOwned>(params: Option<Value>) -> Result<T, serde_json::Error> {
+    let first = params_as::<Vec<Value>>(params)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| serde_json::Error::invalid_length(0, &"at least one param"))?;
+    serde_json::from_value(first)
+}
+
+/// The error for a positional param that's missing.
+fn missing(name: &'static str) -> serde_json::Error {
+    serde_json::Error::missing_field(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_joins_and_leaves() {
+        let player = json!({
+            "name": "x",
+            "id": "3f5108a0-c929-4e77-a115-21f65096887b",
+            "controller": "BP_PlayerController_C_2147482146",
+            "state": "BP_PlayerState_C_2147482120"
+        });
+
+        match Event::from_notification("join", Some(json!([player.clone()]))).unwrap() {
+            Some(Event::Join(joined)) => {
+                assert_eq!(joined.name, "x");
+                assert_eq!(joined.id, "3f5108a0-c929-4e77-a115-21f65096887b");
+                assert_eq!(joined.controller, "BP_PlayerController_C_2147482146");
+                assert_eq!(joined.state, "BP_PlayerState_C_2147482120");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(matches!(
+            Event::from_notification("leave", Some(json!([player]))).unwrap(),
+            Some(Event::Leave(left)) if left.name == "x"
+        ));
+    }
+
+    #[test]
+    fn malformed_joins_are_errors() {
+        assert!(Event::from_notification("join", Some(json!([{"name": "x"}]))).is_err());
+        assert!(Event::from_notification("leave", Some(json!([]))).is_err());
+    }
+
+    #[test]
+    fn parses_interactions() {
+        let params = json!([{
+            "brick_asset": "PB_DefaultMicroBrick",
+            "brick_name": "",
+            "brick_size": [5, 5, 6],
+            "player": {"name": "x", "id": "3f5108a0-c929-4e77-a115-21f65096887b", "controller": "BP_PlayerController_C_1"},
+            "position": [10.0, 20.0, 6.0],
+            "data": {"door": 1},
+            "error": false,
+            "json": true,
+            "message": "{\"door\":1}"
+        }]);
+        let event = Event::from_notification("interact", Some(params)).unwrap();
+        let interaction = match event {
+            Some(Event::Interact(interaction)) => interaction,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(interaction.player.name, "x");
+        assert_eq!(
+            interaction.data_as::<HashMap<String, u32>>().unwrap()["door"],
+            1
+        );
+    }
+
+    #[test]
+    fn malformed_interactions_are_errors() {
+        let params = json!([{"player": {"name": "x"}}]);
+        assert!(Event::from_notification("interact", Some(params)).is_err());
+        assert!(Event::from_notification("interact", None).is_err());
+    }
 }
@@ -0,0 +1,331 @@
+use std::{collections::BTreeMap, fmt::Display, future::Future, pin::Pin};
+
+use tokio::io::AsyncBufRead;
+
+use crate::{commands::CommandCall, events::Event, resources::Player, EventReceiver, Omegga};
+
+/// The output of an event handler: either `()`, or a `Result` whose error is
+/// logged to the Omegga console with [`Omegga::error`](Omegga::error).
+pub trait HandlerResult {
+    fn into_error(self) -> Option<String>;
+}
+
+impl HandlerResult for () {
+    fn into_error(self) -> Option<String> {
+        None
+    }
+}
+
+impl<E: Display> HandlerResult for Result<(), E> {
+    fn into_error(self) -> Option<String> {
+        self.err().map(|e| e.to_string())
+    }
+}
+
+pub(crate) type HandlerFuture = Pin<Box<dyn Future<Output = Option<String>> + Send>>;
+pub(crate) type Handler<A> = Box<dyn Fn(A) -> HandlerFuture + Send + Sync>;
+
+pub(crate) fn boxed<A, F, Fut>(handler: F) -> Handler<A>
+where
+    F: Fn(A) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: HandlerResult,
+{
+    Box::new(move |args| {
+        let future = handler(args);
+        Box::pin(async move { future.await.into_error() })
+    })
+}
+
+/// Handlers registered with the `on_*` methods, driven by [`Omegga::run`](Omegga::run).
+#[derive(Default)]
+pub(crate) struct Handlers {
+    chat: Vec<Handler<(String, String)>>,
+    join: Vec<Handler<Player>>,
+    leave: Vec<Handler<Player>>,
+    /// Kept sorted, so `init` registers the commands in a stable order.
+    commands: BTreeMap<String, Handler<CommandCall>>,
+    events: Vec<Handler<Event>>,
+    shutdown: Vec<Handler<()>>,
+}
+
+impl Omegga {
+    /// Register a handler for chat messages, called with the player's name and the message.
+    pub fn on_chat<F, Fut>(&self, handler: F)
+    where
+        F: Fn(String, String) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        let handler = boxed(move |(player, message)| handler(player, message));
+        self.handlers.write().unwrap().chat.push(handler);
+    }
+
+    /// Register a handler for players joining.
+    pub fn on_join<F, Fut>(&self, handler: F)
+    where
+        F: Fn(Player) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        self.handlers.write().unwrap().join.push(boxed(handler));
+    }
+
+    /// Register a handler for players leaving.
+    pub fn on_leave<F, Fut>(&self, handler: F)
+    where
+        F: Fn(Player) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        self.handlers.write().unwrap().leave.push(boxed(handler));
+    }
+
+    /// Register a handler for a command, called with the player, command and arguments.
+    ///
+    /// The command is registered with Omegga when [`run`](Omegga::run) responds to `init`,
+    /// and handles both `/name` and `!name` invocations. Handlers take the same form as
+    /// those of a [`CommandRouter`](crate::commands::CommandRouter).
+    pub fn on_command<F, Fut>(&self, name: impl Into<String>, handler: F)
+    where
+        F: Fn(CommandCall) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        self.handlers
+            .write()
+            .unwrap()
+            .commands
+            .insert(name.into(), boxed(handler));
+    }
+
+    /// Register a handler for every event, including those without a dedicated `on_*` method.
+    pub fn on_event<F, Fut>(&self, handler: F)
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        self.handlers.write().unwrap().events.push(boxed(handler));
+    }
+
+    /// Register a handler run when the plugin stops, to clean up or save state.
+    ///
+    /// [`run`](Omegga::run) awaits every shutdown handler before answering `stop`, and
+    /// also when Omegga closes stdin or [`shutdown`](Omegga::shutdown) stops the listener.
+    /// They run concurrently, and any still running after the
+    /// [shutdown timeout](crate::OmeggaBuilder::shutdown_timeout) are abandoned.
+    ///
+    /// Only `run` runs them: a plugin reading events from [`spawn`](Omegga::spawn) itself
+    /// has to clean up on its own, as `shutdown` doesn't call them.
+    pub fn on_shutdown<F, Fut>(&self, handler: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HandlerResult,
+    {
+        let handler = boxed(move |()| handler());
+        self.handlers.write().unwrap().shutdown.push(handler);
+    }
+
+    /// Spawn the listener and dispatch events to the registered handlers
+    /// until Omegga stops the plugin.
+    ///
+    /// `init` is answered automatically, registering every command passed to
+    /// [`on_command`](Omegga::on_command), and `run` returns after answering `stop`.
+    /// Each handler runs in its own task, and errors returned from handlers are
+    /// logged to the Omegga console rather than stopping the plugin.
+    pub async fn run(&self) {
+        self.run_events(self.spawn()).await
+    }
+
+    /// Like [`run`](Omegga::run), but reading messages from `reader` instead of stdin,
+    /// as with [`spawn_from`](Omegga::spawn_from).
+    pub async fn run_from<R>(&self, reader: R)
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
+        self.run_events(self.spawn_from(reader)).await
+    }
+
+    async fn run_events(&self, mut events: EventReceiver) {
+        while let Some(event) = events.recv().await {
+            let futures = self.handler_futures(&event);
+            for future in futures {
+                let omegga = self.clone();
+                tokio::spawn(async move {
+                    if let Some(error) = future.await {
+                        omegga.error(error);
+                    }
+                });
+            }
+
+            match event {
+                Event::Init { id, .. } => {
+                    let commands = self
+                        .handlers
+                        .read()
+                        .unwrap()
+                        .commands
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    self.respond_init(id, commands);
+                }
+                Event::Stop { id } => {
+                    self.run_shutdown_handlers().await;
+                    self.respond_stop(id);
+                    return;
+                }
+                _ => (),
+            }
+        }
+
+        // the listener stopped without Omegga stopping the plugin, because stdin
+        // closed or `shutdown` was called
+        self.run_shutdown_handlers().await;
+    }
+
+    /// Run every shutdown handler at once, waiting for them up to the shutdown timeout.
+    async fn run_shutdown_handlers(&self) {
+        let futures = {
+            let handlers = self.handlers.read().unwrap();
+            handlers
+                .shutdown
+                .iter()
+                .map(|handler| handler(()))
+                .collect::<Vec<_>>()
+        };
+
+        let tasks = futures
+            .into_iter()
+            .map(|future| {
+                let omegga = self.clone();
+                tokio::spawn(async move {
+                    if let Some(error) = future.await {
+                        omegga.error(error);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let all = async {
+            for task in tasks {
+                let _ = task.await;
+            }
+        };
+        if tokio::time::timeout(self.shutdown_timeout, all)
+            .await
+            .is_err()
+        {
+            self.error("shutdown handlers timed out");
+        }
+    }
+
+    fn handler_futures(&self, event: &Event) -> Vec<HandlerFuture> {
+        let handlers = self.handlers.read().unwrap();
+        let mut futures = handlers
+            .events
+            .iter()
+            .map(|handler| handler(event.clone()))
+            .collect::<Vec<_>>();
+
+        match event {
+            Event::Chat { player, message } => futures.extend(
+                handlers
+                    .chat
+                    .iter()
+                    .map(|handler| handler((player.clone(), message.clone()))),
+            ),
+            Event::Join(player) => {
+                futures.extend(handlers.join.iter().map(|handler| handler(player.clone())))
+            }
+            Event::Leave(player) => {
+                futures.extend(handlers.leave.iter().map(|handler| handler(player.clone())))
+            }
+            Event::Command {
+                player,
+                command,
+                args,
+            }
+            | Event::ChatCommand {
+                player,
+                command,
+                args,
+            } => {
+                if let Some(handler) = handlers.commands.get(command) {
+                    futures.push(handler(CommandCall {
+                        player: player.clone(),
+                        command: command.clone(),
+                        args: args.clone(),
+                    }));
+                }
+            }
+            _ => (),
+        }
+
+        futures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use serde_json::json;
+
+    use crate::{mock::MockOmegga, rpc};
+
+    #[tokio::test]
+    async fn run_awaits_shutdown_handlers_after_shutdown() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        let ran = Arc::new(AtomicBool::new(false));
+        {
+            let ran = Arc::clone(&ran);
+            omegga.on_shutdown(move || {
+                let ran = Arc::clone(&ran);
+                async move { ran.store(true, Ordering::SeqCst) }
+            });
+        }
+
+        let run = mock.run();
+        tokio::pin!(run);
+        // let the listener start before stopping it
+        tokio::select! {
+            _ = &mut run => panic!("run returned early"),
+            _ = tokio::task::yield_now() => (),
+        }
+        omegga.shutdown();
+        run.await;
+        assert!(ran.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn init_registers_commands_in_order() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        for name in ["zap", "ask", "ping"] {
+            omegga.on_command(name, |_| async {});
+        }
+
+        let run = mock.run();
+        tokio::pin!(run);
+        let init = async {
+            mock.request(7, "init", Some(json!({}))).await.unwrap();
+            mock.wait_for_sent(|message| matches!(message, rpc::Message::Response { .. }))
+                .await
+        };
+        let response = tokio::select! {
+            _ = &mut run => panic!("run returned early"),
+            response = init => response,
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            json!({"jsonrpc": "2.0", "id": 7, "result": {"registeredCommands": ["ask", "ping", "zap"]}})
+        );
+    }
+}
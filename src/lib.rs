@@ -1,320 +1,742 @@
 use std::{
+    collections::{HashMap, VecDeque},
     future::Future,
+    io::{self, Write},
     pin::Pin,
     sync::{
-        atomic::{AtomicI32, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc, Mutex, RwLock,
     },
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 #[cfg(feature = "brs")]
 use brickadia::save;
 
-use dashmap::{mapref::entry::Entry, DashMap};
-use events::{BrickInteraction, Event};
-use resources::{GhostBrick, Player, PlayerPaint, Plugin, TemplateBounds};
+use dashmap::DashMap;
+use events::Event;
+use resources::{
+    BanEntry, Environment, GhostBrick, LoadOptions, Minigame, Player, PlayerAppearance,
+    PlayerPaint, Plugin, RoleSetup, SaveTarget, ServerStatus, TemplateBounds,
+};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{json, Value};
 use thiserror::Error;
 use tokio::{
-    io::{stdin, AsyncBufReadExt, BufReader},
+    io::{stdin, AsyncBufRead, BufReader},
     sync::{
-        mpsc::{self, UnboundedReceiver},
-        oneshot,
+        broadcast,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot, watch,
     },
 };
 
-use crate::resources::PlayerPosition;
+use crate::{
+    events::Subscription,
+    resources::{PlayerPosition, Position},
+};
 
+pub mod commands;
 pub mod events;
+pub mod handlers;
+mod listener;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod ratelimit;
 pub mod resources;
 pub mod rpc;
+pub mod store;
+
+/// Prints a formatted message to the Omegga console, like `println!`:
+/// `olog!(omegga, "loaded {} saves", count)`.
+#[macro_export]
+macro_rules! olog {
+    ($omegga:expr, $($arg:tt)*) => {
+        $omegga.log(format!($($arg)*))
+    };
+}
 
 pub type EventReceiver = UnboundedReceiver<Event>;
+pub type BoundedEventReceiver = mpsc::Receiver<Event>;
+pub type ParseErrorReceiver = UnboundedReceiver<ParseError>;
+pub type RawMessageReceiver = UnboundedReceiver<(String, rpc::Message)>;
+
+/// How many events a subscription can fall behind by before it starts missing them.
+const SUBSCRIPTION_CAPACITY: usize = 256;
+
+/// A hook run on every message passing through, registered with [`OmeggaBuilder`].
+pub type Middleware = Box<dyn Fn(&mut rpc::Message) + Send + Sync>;
+
+/// A line received from Omegga that could not be parsed as an RPC message, or a
+/// notification whose params don't match what Omegga sends for its method.
+#[derive(Error, Debug)]
+#[error("failed to parse message: {error}")]
+pub struct ParseError {
+    pub line: String,
+    #[source]
+    pub error: serde_json::Error,
+}
+
+/// A cloneable handle for queueing outgoing messages, from [`Omegga::writer`](Omegga::writer).
+///
+/// Every clone feeds the same writer task, which writes messages in the order they
+/// were queued. The task ends once every clone is dropped.
+#[derive(Clone)]
+pub struct MessageSender {
+    tx: UnboundedSender<rpc::Message>,
+}
+
+impl MessageSender {
+    /// Queue a message to be written, failing with [`WriteError::Closed`](WriteError::Closed)
+    /// if the writer task is gone.
+    pub fn send(&self, message: rpc::Message) -> Result<(), WriteError> {
+        self.tx.send(message).map_err(|_| WriteError::Closed)
+    }
+
+    /// Queue a notification to be written.
+    pub fn notify(
+        &self,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> Result<(), WriteError> {
+        self.send(rpc::Message::notification(method.into(), params))
+    }
+}
 
 /// A future that waits for the server to respond, returning a [`Response`](crate::Response).
-/// This will await indefinitely, so use with Tokio's `select!` macro to impose a timeout.
-pub struct ResponseAwaiter(oneshot::Receiver<rpc::Response>);
+/// This will await indefinitely unless given a [`timeout`](ResponseAwaiter::timeout), or a
+/// default one is set with [`OmeggaBuilder::request_timeout`](OmeggaBuilder::request_timeout).
+///
+/// Dropping the awaiter before it completes removes its pending entry, so a lost
+/// `select!` branch doesn't leave a dead sender behind.
+pub struct ResponseAwaiter {
+    rx: oneshot::Receiver<rpc::Response>,
+    id: rpc::RequestId,
+    awaiter_txs: Arc<AwaiterMap>,
+    completed: bool,
+    sent: Instant,
+    timeout: Option<Duration>,
+    deadline: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl ResponseAwaiter {
+    /// Fail with [`ResponseError::Timeout`](ResponseError::Timeout) if the response takes
+    /// longer than `timeout` from when the request was sent, overriding any default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self.deadline = None;
+        self
+    }
+
+    /// Wait for the response indefinitely, even with a default request timeout set.
+    pub fn no_timeout(mut self) -> Self {
+        self.timeout = None;
+        self.deadline = None;
+        self
+    }
+}
 
 impl Future for ResponseAwaiter {
     type Output = Result<Option<Value>, ResponseError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match Pin::new(&mut self.0).poll(cx) {
+        let poll = Pin::new(&mut self.rx).poll(cx);
+
+        // either way, the listener has already removed our entry
+        if poll.is_ready() {
+            self.completed = true;
+        }
+
+        match poll {
             // we received a response, filter between a real result or an RPC error
-            Poll::Ready(Ok(response)) => Poll::Ready(match response.error {
-                Some(e) => Err(ResponseError::Rpc(e)),
-                None => Ok(response.result),
-            }),
+            Poll::Ready(Ok(response)) => {
+                #[cfg(feature = "log")]
+                log::debug!("response to #{} after {:?}", self.id, self.sent.elapsed());
+                Poll::Ready(response.into_result())
+            }
 
             // no response received, the channel errored
             Poll::Ready(Err(error)) => Poll::Ready(Err(ResponseError::Recv(error))),
 
-            // we are still waiting
-            Poll::Pending => Poll::Pending,
+            // we are still waiting, unless the deadline has passed
+            Poll::Pending => {
+                if let Some(timeout) = self.timeout {
+                    let deadline = self.sent + timeout;
+                    let sleep = self
+                        .deadline
+                        .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline.into())));
+                    // the entry is left for the drop to remove
+                    if sleep.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Err(ResponseError::Timeout));
+                    }
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for ResponseAwaiter {
+    fn drop(&mut self) {
+        // if a response races with the drop, the listener has already removed
+        // the entry and this is a no-op. closing first means only our own
+        // sender is removed, not one registered later under a reused id
+        if !self.completed {
+            self.rx.close();
+            self.awaiter_txs.remove_if(&self.id, |_, tx| tx.is_closed());
+            #[cfg(feature = "log")]
+            log::debug!("request #{} dropped before its response", self.id);
         }
     }
 }
 
-/// A response error. Either an RPC error (`rpc::Error`), or a receive error (`oneshot::error::RecvError`).
+/// A future that waits for every response to a batch request, returning
+/// the results in the same order as the calls.
+///
+/// If the server rejects the whole batch with a single error, every call that
+/// hasn't been answered yet resolves to that error. Such an error has no ID, so it
+/// goes to the oldest batch still waiting for a response; if a single request is
+/// rejected the same way while a batch is waiting, the batch gets its error.
+pub struct BatchAwaiter {
+    awaiters: Vec<Option<ResponseAwaiter>>,
+    results: Vec<Option<Result<Option<Value>, ResponseError>>>,
+    batch_error: Option<oneshot::Receiver<rpc::Error>>,
+    batch_error_txs: Arc<BatchErrorQueue>,
+}
+
+impl BatchAwaiter {
+    /// Wait for the batch until `timeout` elapses, then resolve every call still pending to
+    /// [`ResponseError::Timeout`](ResponseError::Timeout), removing their pending entries.
+    pub async fn with_timeout(
+        mut self,
+        timeout: Duration,
+    ) -> Vec<Result<Option<Value>, ResponseError>> {
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(results) = Pin::new(&mut self).poll(cx) {
+                return Poll::Ready(results);
+            }
+
+            match deadline.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(self.take_partial()),
+                Poll::Pending => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Take the results received so far, timing out the rest.
+    fn take_partial(&mut self) -> Vec<Result<Option<Value>, ResponseError>> {
+        // dropping the awaiters removes their entries
+        self.awaiters.clear();
+        self.release_batch_error();
+        self.results
+            .iter_mut()
+            .map(|r| r.take().unwrap_or(Err(ResponseError::Timeout)))
+            .collect()
+    }
+
+    /// Stop waiting for an error for the whole batch, removing the batch from the queue.
+    fn release_batch_error(&mut self) {
+        if self.batch_error.take().is_some() {
+            // our receiver is gone, so this drops our sender along with any other finished batch
+            if let Ok(mut queue) = self.batch_error_txs.lock() {
+                queue.retain(|batch| !batch.tx.is_closed());
+            }
+        }
+    }
+}
+
+impl Drop for BatchAwaiter {
+    fn drop(&mut self) {
+        self.release_batch_error();
+    }
+}
+
+impl Future for BatchAwaiter {
+    type Output = Vec<Result<Option<Value>, ResponseError>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        // the whole batch was rejected, so fail everything still pending
+        if let Some(batch_error) = &mut this.batch_error {
+            match Pin::new(batch_error).poll(cx) {
+                Poll::Ready(Ok(error)) => {
+                    this.batch_error = None;
+                    for (awaiter, result) in this.awaiters.iter_mut().zip(&mut this.results) {
+                        if awaiter.take().is_some() {
+                            *result = Some(Err(ResponseError::Rpc(error.clone())));
+                        }
+                    }
+                }
+                Poll::Ready(Err(_)) => this.batch_error = None,
+                Poll::Pending => (),
+            }
+        }
+
+        for (awaiter, result) in this.awaiters.iter_mut().zip(&mut this.results) {
+            if let Some(inner) = awaiter {
+                if let Poll::Ready(r) = Pin::new(inner).poll(cx) {
+                    *awaiter = None;
+                    *result = Some(r);
+                }
+            }
+        }
+
+        if this.awaiters.iter().all(Option::is_none) {
+            this.release_batch_error();
+            Poll::Ready(this.results.iter_mut().map(|r| r.take().unwrap()).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A response error. Either an RPC error (`rpc::Error`), a receive error (`oneshot::error::RecvError`),
+/// a timeout, a missing result, a failure to deserialize the response into the expected type,
+/// a missing target plugin, or a request rejected before it was sent.
 #[derive(Error, Debug)]
 pub enum ResponseError {
-    #[error("rpc error")]
+    #[error(transparent)]
     Rpc(rpc::Error),
 
     #[error("receive error")]
     Recv(#[from] oneshot::error::RecvError),
+
+    #[error("request timed out")]
+    Timeout,
+
+    #[error("response had no result")]
+    NullResult,
+
+    #[error("failed to deserialize response")]
+    Deserialize(#[from] serde_json::Error),
+
+    #[error("plugin not found: {0}")]
+    PluginNotFound(String),
+
+    #[error("role not found: {0}")]
+    RoleNotFound(String),
+
+    #[error("failed to send request")]
+    Write(#[from] WriteError),
+
+    #[error("the listener isn't running")]
+    NotListening,
+}
+
+type AwaiterMap = DashMap<rpc::RequestId, oneshot::Sender<rpc::Response>>;
+type BatchErrorQueue = Mutex<VecDeque<PendingBatch>>;
+
+/// A batch waiting for its responses, which an error for the whole batch is sent to.
+struct PendingBatch {
+    ids: Vec<rpc::RequestId>,
+    tx: oneshot::Sender<rpc::Error>,
+}
+
+/// A write error, from validating, serializing or writing out a message to Omegga.
+#[derive(Error, Debug)]
+pub enum WriteError {
+    #[error("invalid argument: {0}")]
+    InvalidArgument(&'static str),
+
+    #[error("failed to serialize message")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("failed to write message")]
+    Io(#[from] io::Error),
+
+    #[error("invalid params for {method}, expected {expected:?}")]
+    InvalidParams {
+        method: String,
+        expected: rpc::ParamsShape,
+    },
+
+    #[error("the writer task has stopped")]
+    Closed,
 }
 
+/// A connection to Omegga.
+///
+/// Cloning is cheap, and every clone shares the same state: pending requests, the request ID
+/// counter, the output, handlers, subscriptions and the listener. A request made from any
+/// clone is answered through the one listener, so pass clones into spawned tasks freely.
+///
+/// There are no methods for stopping, starting or restarting the server, since Omegga
+/// doesn't expose server control over its plugin RPC. Plugins can still react to
+/// Omegga's automatic restarts with [`Event::Autorestart`](Event::Autorestart).
+#[derive(Clone)]
 pub struct Omegga {
-    pub awaiter_txs: Arc<DashMap<rpc::RequestId, oneshot::Sender<rpc::Response>>>,
+    awaiter_txs: Arc<AwaiterMap>,
+    batch_error_txs: Arc<BatchErrorQueue>,
     request_id: Arc<AtomicI32>,
+    id_step: i32,
+    shutdown_tx: Arc<watch::Sender<()>>,
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+    buffered: Arc<Mutex<Vec<u8>>>,
+    handlers: Arc<RwLock<handlers::Handlers>>,
+    listening: Arc<AtomicBool>,
+    outgoing: Arc<[Middleware]>,
+    incoming: Arc<[Middleware]>,
+    subscribers: broadcast::Sender<Event>,
+    forward_orphans: bool,
+    shutdown_timeout: Duration,
+    chat_limiter: Option<Arc<ratelimit::RateLimiter>>,
+    init: Arc<watch::Sender<Option<events::InitData>>>,
+    request_timeout: Option<Duration>,
+    #[cfg(feature = "metrics")]
+    counters: Arc<metrics::Counters>,
 }
 
 impl Omegga {
     /// Create a new Omegga instance.
     pub fn new() -> Self {
-        Self {
-            awaiter_txs: Arc::new(DashMap::new()),
-            request_id: Arc::new(AtomicI32::new(-1)),
-        }
+        OmeggaBuilder::new().build()
+    }
+
+    /// Create a builder for an Omegga instance with non-default settings.
+    pub fn builder() -> OmeggaBuilder {
+        OmeggaBuilder::new()
     }
 
     /// Spawn the listener.
+    ///
+    /// The listener stops when Omegga closes stdin (or reading from it fails), sending
+    /// [`Event::Disconnected`](Event::Disconnected), after which the returned receiver
+    /// yields `None`. Pending requests fail with a receive error, as with
+    /// [`shutdown`](Omegga::shutdown). A closed stdin can't be reopened, so there's
+    /// nothing to reconnect to; use [`spawn_from`](Omegga::spawn_from) again with a new
+    /// reader for other transports.
+    ///
+    /// Only one listener may run at a time. Spawning while one is running logs an
+    /// error and returns a receiver that is already closed.
     pub fn spawn(&self) -> EventReceiver {
+        self.spawn_from(BufReader::new(stdin()))
+    }
+
+    /// Spawn the listener, reading messages from `reader` instead of stdin.
+    /// Otherwise identical to [`spawn`](Omegga::spawn).
+    pub fn spawn_from<R>(&self, reader: R) -> EventReceiver
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
+        self.listen(reader, None)
+    }
+
+    /// Spawn the listener with a bounded event channel, holding at most `capacity` events.
+    ///
+    /// When the channel is full, the listener waits for the consumer to catch up
+    /// before reading any more messages, applying backpressure onto Omegga instead
+    /// of buffering a burst of events without bound. Responses are read by the same
+    /// listener, so don't await a request while leaving the channel full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is `0`.
+    pub fn spawn_bounded(&self, capacity: usize) -> BoundedEventReceiver {
+        let (tx, rx) = mpsc::channel::<Event>(capacity);
+        self.listen_with(
+            BufReader::new(stdin()),
+            listener::EventSender::Bounded(tx),
+            None,
+            None,
+        );
+        rx
+    }
+
+    /// Spawn the listener, yielding every message read along with the exact line it was
+    /// parsed from, instead of events.
+    ///
+    /// This is for logging or forwarding what Omegga sent byte for byte, since
+    /// re-serializing a message can differ from the original. Messages are yielded before
+    /// the [incoming hooks](OmeggaBuilder::incoming) run, and each message of a batch
+    /// comes with the whole batch's line. Responses still reach their requests, and
+    /// [subscriptions](Omegga::subscribe) still receive events, but nothing answers `init`.
+    pub fn spawn_raw(&self) -> RawMessageReceiver {
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        self.listen_with(
+            BufReader::new(stdin()),
+            listener::EventSender::Discard,
+            None,
+            Some(raw_tx),
+        );
+        raw_rx
+    }
+
+    /// Spawn the listener, also returning a receiver for lines that failed to parse
+    /// as RPC messages or events. Otherwise identical to [`spawn`](Omegga::spawn).
+    pub fn spawn_with_errors(&self) -> (EventReceiver, ParseErrorReceiver) {
+        let (error_tx, error_rx) = mpsc::unbounded_channel::<ParseError>();
+        (
+            self.listen(BufReader::new(stdin()), Some(error_tx)),
+            error_rx,
+        )
+    }
+
+    fn listen<R>(&self, reader: R, errors: Option<UnboundedSender<ParseError>>) -> EventReceiver
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
         let (tx, rx) = mpsc::unbounded_channel::<Event>();
-        let awaiter_txs = Arc::clone(&self.awaiter_txs);
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdin());
-            let mut lines = reader.lines();
-            while let Some(line) = lines.next_line().await.unwrap() {
-                let message: rpc::Message = match serde_json::from_str(&line) {
-                    Ok(v) => v,
-                    Err(_) => continue,
-                };
-
-                match message {
-                    // Handle responses
-                    rpc::Message::Response {
-                        id, result, error, ..
-                    } => {
-                        if let Entry::Occupied(entry) = awaiter_txs.entry(id) {
-                            let (id, sender) = entry.remove_entry();
-                            let _ = sender.send(rpc::Response { id, result, error });
-                        }
-                    }
-                    // Handle requests
-                    rpc::Message::Request {
-                        id, method, params, ..
-                    } => match method.as_str() {
-                        "init" => {
-                            let _ = tx.send(Event::Init {
-                                id,
-                                config: params.unwrap_or(Value::Null),
-                            });
-                        }
-                        "stop" => {
-                            let _ = tx.send(Event::Stop { id });
-                        }
-                        "plugin:emit" => match params {
-                            Some(Value::Array(v)) => {
-                                let mut params = v.into_iter();
-                                let event = match params.next().unwrap() {
-                                    Value::String(s) => s,
-                                    _ => continue,
-                                };
-                                let from = match params.next().unwrap() {
-                                    Value::String(s) => s,
-                                    _ => continue,
-                                };
-
-                                let _ = tx.send(Event::PluginEmit {
-                                    id,
-                                    event,
-                                    from,
-                                    args: params.collect(),
-                                });
-                            }
-                            _ => (),
-                        },
-                        _ => (),
-                    },
-                    // Handle notifications
-                    rpc::Message::Notification { method, params, .. } => match method.as_str() {
-                        "bootstrap" => {
-                            let _ = tx.send(Event::Bootstrap {
-                                omegga: params.unwrap_or(Value::Null),
-                            });
-                        }
-                        "plugin:players:raw" => {
-                            let _ = tx.send(Event::PluginPlayersRaw {
-                                players: serde_json::from_value(params.unwrap())
-                                    .unwrap_or_default(),
-                            });
-                        }
-                        "line" => {
-                            let _ = tx.send(Event::Line(
-                                serde_json::from_value::<Vec<String>>(params.unwrap())
-                                    .unwrap()
-                                    .into_iter()
-                                    .next()
-                                    .unwrap(),
-                            ));
-                        }
-                        "start" => {
-                            #[derive(serde::Deserialize)]
-                            struct MapParams {
-                                map: String,
-                            }
-
-                            let _ = tx.send(Event::Start {
-                                map: serde_json::from_value::<Vec<MapParams>>(params.unwrap())
-                                    .unwrap()
-                                    .into_iter()
-                                    .next()
-                                    .unwrap()
-                                    .map,
-                            });
-                        }
-                        "host" => {
-                            #[derive(serde::Deserialize)]
-                            struct HostParams {
-                                name: String,
-                                id: String,
-                            }
-
-                            let host = serde_json::from_value::<Vec<HostParams>>(params.unwrap())
-                                .unwrap()
-                                .into_iter()
-                                .next()
-                                .unwrap();
-
-                            let _ = tx.send(Event::Host {
-                                name: host.name,
-                                id: host.id,
-                            });
-                        }
-                        "version" => {
-                            let _ = tx.send(Event::Version(params.unwrap()));
-                        }
-                        "unauthorized" => {
-                            let _ = tx.send(Event::Unauthorized);
-                        }
-                        "join" => {
-                            let _ = tx.send(Event::Join(
-                                serde_json::from_value::<Vec<_>>(params.unwrap())
-                                    .unwrap()
-                                    .into_iter()
-                                    .next()
-                                    .unwrap(),
-                            ));
-                        }
-                        "leave" => {
-                            let _ = tx.send(Event::Leave(
-                                serde_json::from_value::<Vec<_>>(params.unwrap())
-                                    .unwrap()
-                                    .into_iter()
-                                    .next()
-                                    .unwrap(),
-                            ));
-                        }
-                        e if e.starts_with("cmd:") => {
-                            let c = &e[4..];
-                            let mut params = serde_json::from_value::<Vec<String>>(params.unwrap())
-                                .unwrap()
-                                .into_iter();
-
-                            let _ = tx.send(Event::Command {
-                                player: params.next().unwrap(),
-                                command: c.to_string(),
-                                args: params.collect(),
-                            });
-                        }
-                        e if e.starts_with("chatcmd:") => {
-                            let c = &e[8..];
-                            let mut params = serde_json::from_value::<Vec<String>>(params.unwrap())
-                                .unwrap()
-                                .into_iter();
-
-                            let _ = tx.send(Event::ChatCommand {
-                                player: params.next().unwrap(),
-                                command: c.to_string(),
-                                args: params.collect(),
-                            });
-                        }
-                        "chat" => {
-                            let mut params = serde_json::from_value::<Vec<String>>(params.unwrap())
-                                .unwrap()
-                                .into_iter();
-
-                            let _ = tx.send(Event::Chat {
-                                player: params.next().unwrap(),
-                                message: params.next().unwrap(),
-                            });
-                        }
-                        "mapchange" => {
-                            #[derive(serde::Deserialize)]
-                            struct MapParams {
-                                map: String,
-                            }
-
-                            let _ = tx.send(Event::MapChange(
-                                serde_json::from_value::<Vec<MapParams>>(params.unwrap())
-                                    .unwrap()
-                                    .into_iter()
-                                    .next()
-                                    .unwrap()
-                                    .map,
-                            ));
-                        }
-                        "interact" => {
-                            match params {
-                              Some(Value::Array(params)) => {
-                                  let mut params = params.into_iter();
-                                  let interact =
-                                      serde_json::from_value::<BrickInteraction>(params.next().unwrap())
-                                          .unwrap();
-
-                                  let _ = tx.send(Event::Interact(interact));
-                              }
-                              _ => continue,
-                            }
-                        }
-                        e if e.starts_with("event:") => {
-                            let e = &e[6..];
-                            match params {
-                                Some(Value::Array(params)) => {
-                                    let mut params = params.into_iter();
-                                    let player =
-                                        serde_json::from_value::<Player>(params.next().unwrap())
-                                            .unwrap();
-                                    let args = params
-                                        .map(|a| String::from(a.as_str().unwrap()))
-                                        .collect::<Vec<_>>();
-
-                                    let _ = tx.send(Event::Event {
-                                        name: String::from(e),
-                                        player,
-                                        args,
-                                    });
-                                }
-                                _ => continue,
-                            }
-                        }
-                        "autorestart" => {
-                            let _ = tx.send(Event::Autorestart(params.unwrap_or_default()));
-                        }
-                        _ => (),
-                    },
-                };
-            }
-        });
+        self.listen_with(reader, listener::EventSender::Unbounded(tx), errors, None);
         rx
     }
 
+    fn listen_with<R>(
+        &self,
+        reader: R,
+        events: listener::EventSender,
+        errors: Option<UnboundedSender<ParseError>>,
+        raw: Option<UnboundedSender<(String, rpc::Message)>>,
+    ) where
+        R: AsyncBufRead + Unpin + Send + 'static,
+    {
+        // a second listener would race the first for lines, so refuse to start
+        // one, closing the new event channel by dropping its sender
+        if self.listening.swap(true, Ordering::SeqCst) {
+            self.error("omegga-rs: the listener is already running, ignoring this spawn");
+            return;
+        }
+
+        let listener = listener::Listener {
+            awaiter_txs: Arc::clone(&self.awaiter_txs),
+            batch_error_txs: Arc::clone(&self.batch_error_txs),
+            events,
+            errors,
+            raw,
+            shutdown: self.shutdown_tx.subscribe(),
+            listening: Arc::clone(&self.listening),
+            incoming: Arc::clone(&self.incoming),
+            subscribers: self.subscribers.clone(),
+            forward_orphans: self.forward_orphans,
+            init: Arc::clone(&self.init),
+            #[cfg(feature = "metrics")]
+            counters: Arc::clone(&self.counters),
+        };
+        tokio::spawn(listener.run(reader));
+    }
+
+    /// The plugin's config from the web UI, as delivered with `init`, or `None` before
+    /// the listener has read `init`.
+    pub fn raw_config(&self) -> Option<Value> {
+        self.init.borrow().as_ref().map(|init| init.config.clone())
+    }
+
+    /// Deserialize the plugin's config, as delivered with `init`, into `T`, or `Ok(None)`
+    /// before the listener has read `init`.
+    ///
+    /// Fields in the config that `T` doesn't have are ignored. Mark fields of `T` with
+    /// `#[serde(default)]` to tolerate them missing, such as for settings added since the
+    /// config was last saved.
+    pub fn config<T: DeserializeOwned>(&self) -> Result<Option<T>, serde_json::Error> {
+        self.raw_config().map(serde_json::from_value).transpose()
+    }
+
+    /// Wait for the listener to read Omegga's `init` request, returning its ID and the
+    /// plugin's config, or return them straight away if it already has.
+    ///
+    /// This doesn't take anything from the event channel, so [`Event::Init`](Event::Init)
+    /// is still delivered there, along with every event before it. Respond to `init` once,
+    /// either with the returned ID or from the event.
+    pub async fn await_init(&self) -> events::InitData {
+        let mut init = self.init.subscribe();
+        loop {
+            if let Some(data) = init.borrow_and_update().clone() {
+                return data;
+            }
+            // `self` holds the sender, so the channel can't close
+            let _ = init.changed().await;
+        }
+    }
+
+    /// Subscribe to the events named `name` (see [`Event::name`](Event::name)), like `"join"`,
+    /// alongside the event channel returned by [`spawn`](Omegga::spawn).
+    ///
+    /// Each subscription receives matching events in the order the listener read them,
+    /// independently of any other subscription, starting from when it was created.
+    pub fn subscribe(&self, name: impl Into<String>) -> Subscription {
+        Subscription::new(self.subscribers.subscribe(), Some(name.into()))
+    }
+
+    /// Subscribe to every event, alongside the event channel returned by [`spawn`](Omegga::spawn).
+    pub fn subscribe_all(&self) -> Subscription {
+        Subscription::new(self.subscribers.subscribe(), None)
+    }
+
+    /// Wait for the first event matching `predicate`, or `None` if none arrives within
+    /// `timeout`. The event is still sent to the event channel as usual.
+    ///
+    /// Only events received after this is called are matched, so call it before sending
+    /// the command that causes the event, and await it afterwards.
+    pub fn wait_for_event<F>(
+        &self,
+        predicate: F,
+        timeout: Duration,
+    ) -> impl Future<Output = Option<Event>>
+    where
+        F: Fn(&Event) -> bool,
+    {
+        let mut subscription = self.subscribe_all();
+        async move {
+            let matching = async {
+                while let Some(event) = subscription.recv().await {
+                    if predicate(&event) {
+                        return Some(event);
+                    }
+                }
+                None
+            };
+            tokio::time::timeout(timeout, matching).await.ok().flatten()
+        }
+    }
+
+    /// Read the message counters, shared by every clone of this `Omegga`.
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> metrics::Stats {
+        self.counters.snapshot()
+    }
+
+    /// Stop every running listener, closing their event channels, and fail every
+    /// pending request with a receive error instead of leaving it pending forever.
+    ///
+    /// This doesn't run the handlers registered with [`on_shutdown`](Omegga::on_shutdown)
+    /// itself, and returns without waiting for anything. A [`run`](Omegga::run) in
+    /// progress still runs them once its event channel closes, before returning, so
+    /// await that `run` to know they're done.
+    ///
+    /// The listener can be spawned again as soon as this returns, even while the old one
+    /// is still winding down.
+    pub fn shutdown(&self) {
+        self.shutdown_tx.send_replace(());
+        self.listening.store(false, Ordering::SeqCst);
+
+        // dropping the transmitters resolves their awaiters
+        self.awaiter_txs.clear();
+        self.batch_error_txs.lock().unwrap().clear();
+    }
+
     /// Write out an RPC message.
+    ///
+    /// A message that fails to serialize or write is reported on stderr and dropped,
+    /// so use [`try_write`](Omegga::try_write) to handle the error instead.
     pub fn write(&self, message: rpc::Message) {
-        println!("{}", serde_json::to_string(&message).unwrap());
+        if let Err(error) = self.try_write(message) {
+            eprintln!("failed to write to Omegga: {}", error);
+        }
+    }
+
+    /// Spawn a writer task and return a [`MessageSender`] queueing messages for it, for
+    /// worker tasks that emit messages without awaiting responses.
+    ///
+    /// Messages go through [`write`](Omegga::write), so the outgoing hooks still apply.
+    pub fn writer(&self) -> MessageSender {
+        let (tx, mut rx) = mpsc::unbounded_channel::<rpc::Message>();
+        let omegga = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                omegga.write(message);
+            }
+        });
+        MessageSender { tx }
+    }
+
+    /// Write out an RPC message, returning an error if it fails to serialize or write.
+    /// Nothing is written if serialization fails.
+    pub fn try_write(&self, mut message: rpc::Message) -> Result<(), WriteError> {
+        self.apply_outgoing(&mut message);
+        self.write_line(&message)
+    }
+
+    /// Write out an RPC message like [`try_write`](Omegga::try_write), first checking the
+    /// params of a request or notification against [`rpc::validate_params`](rpc::validate_params).
+    /// Returns an error without writing anything if they don't match what the method expects.
+    pub fn write_checked(&self, message: rpc::Message) -> Result<(), WriteError> {
+        match &message {
+            rpc::Message::Request { method, params, .. }
+            | rpc::Message::Notification { method, params, .. } => {
+                rpc::validate_params(method, params.as_ref())?
+            }
+            rpc::Message::Response { .. } => (),
+        }
+        self.try_write(message)
+    }
+
+    /// Write out an RPC message without blocking the runtime.
+    ///
+    /// [`write`](Omegga::write) (and every helper built on it) blocks the current thread
+    /// if Omegga is slow to drain the pipe. This instead does the write on Tokio's blocking
+    /// thread pool, at the cost of being `async` and of an extra thread hop per message.
+    pub async fn write_async(&self, mut message: rpc::Message) -> io::Result<()> {
+        self.apply_outgoing(&mut message);
+        let mut line = serde_json::to_vec(&message)?;
+        line.push(b'\n');
+
+        #[cfg(feature = "metrics")]
+        self.counters.add_bytes(line.len());
+
+        let output = Arc::clone(&self.output);
+        tokio::task::spawn_blocking(move || {
+            let mut output = output.lock().unwrap();
+            output.write_all(&line)?;
+            output.flush()
+        })
+        .await?
+    }
+
+    /// Queue an RPC message to be written on the next [`flush`](Omegga::flush), to batch many
+    /// messages into a single write.
+    ///
+    /// The message is serialized right away, so only whole lines are ever queued. Messages
+    /// written with [`write`](Omegga::write) in the meantime go out ahead of the queue.
+    pub fn buffer_write(&self, mut message: rpc::Message) -> Result<(), WriteError> {
+        self.apply_outgoing(&mut message);
+        let mut line = serde_json::to_vec(&message)?;
+        line.push(b'\n');
+
+        self.buffered.lock().unwrap().extend_from_slice(&line);
+        Ok(())
+    }
+
+    /// Write out every message queued with [`buffer_write`](Omegga::buffer_write) at once,
+    /// and flush the output.
+    pub fn flush(&self) -> Result<(), WriteError> {
+        // take the queue under the output lock, so concurrent flushes keep it in order
+        let mut output = self.output.lock().unwrap();
+        let buffered = std::mem::take(&mut *self.buffered.lock().unwrap());
+        #[cfg(feature = "metrics")]
+        self.counters.add_bytes(buffered.len());
+        output.write_all(&buffered)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Run the outgoing middleware over a message, in registration order.
+    fn apply_outgoing(&self, message: &mut rpc::Message) {
+        for middleware in self.outgoing.iter() {
+            middleware(message);
+        }
+    }
+
+    /// Serialize a value onto the output as a single line, holding the output lock
+    /// for the whole line so concurrent writers can't interleave.
+    fn write_line(&self, value: &impl Serialize) -> Result<(), WriteError> {
+        // serialize up front, so a failure can't leave half a line on the output
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+
+        #[cfg(feature = "metrics")]
+        self.counters.add_bytes(line.len());
+
+        let mut output = self.output.lock().unwrap();
+        output.write_all(&line)?;
+        output.flush()?;
+        Ok(())
     }
 
     /// Write out an RPC notification.
@@ -322,14 +744,51 @@ impl Omegga {
         self.write(rpc::Message::notification(method.into(), params));
     }
 
+    /// Write out an RPC notification, first checking its params like
+    /// [`write_checked`](Omegga::write_checked).
+    pub fn write_notification_checked(
+        &self,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> Result<(), WriteError> {
+        self.write_checked(rpc::Message::notification(method.into(), params))
+    }
+
+    /// Write out an RPC response, with either a result or an error.
+    pub fn respond(&self, id: impl Into<rpc::RequestId>, body: rpc::ResponseBody) {
+        let (result, error) = match body {
+            rpc::ResponseBody::Ok(result) => (result, None),
+            rpc::ResponseBody::Err(error) => (None, Some(error)),
+        };
+        self.write(rpc::Message::response(id.into(), result, error));
+    }
+
     /// Write out an RPC response.
+    ///
+    /// Per JSON-RPC, a response carries either a result or an error, never both, so
+    /// prefer [`respond`](Omegga::respond), which can't be given both.
+    #[deprecated(note = "use `respond` with a `ResponseBody`")]
     pub fn write_response(
         &self,
-        id: rpc::RequestId,
+        id: impl Into<rpc::RequestId>,
         params: Option<Value>,
         error: Option<rpc::Error>,
     ) {
-        self.write(rpc::Message::response(id, params, error));
+        debug_assert!(
+            params.is_none() || error.is_none(),
+            "a response can't have both a result and an error"
+        );
+        self.write(rpc::Message::response(id.into(), params, error));
+    }
+
+    /// Write out a successful RPC response.
+    pub fn write_response_ok(&self, id: impl Into<rpc::RequestId>, result: Option<Value>) {
+        self.respond(id, rpc::ResponseBody::Ok(result));
+    }
+
+    /// Write out an RPC error response.
+    pub fn write_response_err(&self, id: impl Into<rpc::RequestId>, error: rpc::Error) {
+        self.respond(id, rpc::ResponseBody::Err(error));
     }
 
     /// Write out an RPC request.
@@ -339,35 +798,330 @@ impl Omegga {
     /// await a response from the RPC server.
     pub fn write_request(
         &self,
-        id: rpc::RequestId,
+        id: impl Into<rpc::RequestId>,
         method: impl Into<String>,
         params: Option<Value>,
     ) {
-        self.write(rpc::Message::request(id, method.into(), params));
+        let (id, method) = (id.into(), method.into());
+        #[cfg(feature = "log")]
+        log::debug!("request #{}: {}", id, method);
+        self.write(rpc::Message::request(id, method, params));
     }
 
     /// Request a response from the RPC server.
     /// This returns a `ResponseAwaiter`, a `Future` that awaits a response.
+    ///
+    /// The response is read by the listener, so without one running (see [`spawn`](Omegga::spawn))
+    /// the awaiter never completes. Use [`try_request`](Omegga::try_request) to fail instead.
     pub fn request(&self, method: impl Into<String>, params: Option<Value>) -> ResponseAwaiter {
-        // fetch the next ID
-        let id = self.request_id.fetch_sub(-1, Ordering::SeqCst);
+        let id = self.next_request_id();
+        self.request_with_id(id, method, params)
+    }
+
+    /// Request a response from the RPC server like [`request`](Omegga::request), first checking
+    /// the params against [`rpc::validate_params`](rpc::validate_params). Returns an error without
+    /// sending anything if they don't match what the method expects.
+    pub fn request_checked(
+        &self,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> Result<ResponseAwaiter, WriteError> {
+        let method = method.into();
+        rpc::validate_params(&method, params.as_ref())?;
+        Ok(self.request(method, params))
+    }
+
+    /// Make a request, failing with [`ResponseError::NotListening`](ResponseError::NotListening)
+    /// without writing anything if no listener is running to read its response.
+    pub fn try_request(
+        &self,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> Result<ResponseAwaiter, ResponseError> {
+        if !self.is_listening() {
+            return Err(ResponseError::NotListening);
+        }
+        Ok(self.request(method, params))
+    }
+
+    /// Whether a listener is running, started by [`spawn`](Omegga::spawn) or one of its variants.
+    pub fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::SeqCst)
+    }
+
+    /// Make a request with named params serialized from `params`, such as a
+    /// `#[derive(Serialize)]` struct.
+    ///
+    /// Returns an error without writing anything if `params` fails to serialize or
+    /// doesn't serialize to an object.
+    pub fn request_named<P: Serialize>(
+        &self,
+        method: impl Into<String>,
+        params: &P,
+    ) -> Result<ResponseAwaiter, WriteError> {
+        match serde_json::to_value(params)? {
+            params @ Value::Object(_) => Ok(self.request(method, Some(params))),
+            _ => Err(WriteError::InvalidArgument(
+                "named params must serialize to an object",
+            )),
+        }
+    }
+
+    /// Request a response from the RPC server like [`request`](Omegga::request), building
+    /// the params with `params` right before the request is written, so they're never built
+    /// when the request isn't made.
+    pub fn request_with<F>(&self, method: impl Into<String>, params: F) -> ResponseAwaiter
+    where
+        F: FnOnce() -> Option<Value>,
+    {
+        let id = self.next_request_id();
+        let awaiter = self.register_awaiter(id.clone());
+        self.write_request(id, method, params());
+        awaiter
+    }
+
+    /// Request a response from the RPC server, deserializing its result into `T`.
+    ///
+    /// A missing or `null` result is reported as [`ResponseError::NullResult`](ResponseError::NullResult),
+    /// so use [`request`](Omegga::request) directly if a null result is expected.
+    pub async fn request_typed<T: DeserializeOwned>(
+        &self,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> Result<T, ResponseError> {
+        match self.request(method, params).await? {
+            None | Some(Value::Null) => Err(ResponseError::NullResult),
+            Some(result) => Ok(serde_json::from_value::<T>(result)?),
+        }
+    }
+
+    /// Request a response from the RPC server, giving up after `timeout`.
+    ///
+    /// If no response arrives in time, the pending awaiter is removed and
+    /// [`ResponseError::Timeout`](ResponseError::Timeout) is returned.
+    pub async fn request_with_timeout(
+        &self,
+        method: impl Into<String>,
+        params: Option<Value>,
+        timeout: Duration,
+    ) -> Result<Option<Value>, ResponseError> {
+        self.request(method, params).timeout(timeout).await
+    }
+
+    /// Measure the round trip to Omegga, failing with [`ResponseError::Timeout`](ResponseError::Timeout)
+    /// if it takes longer than `timeout`.
+    ///
+    /// This looks up a plugin with an empty name, which Omegga answers without touching the
+    /// server, so the time is mostly the RPC link and Omegga's own responsiveness. An error
+    /// response still counts as a round trip.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration, ResponseError> {
+        let start = Instant::now();
+        match self
+            .request_with_timeout("plugin.get", Some(Value::String(String::new())), timeout)
+            .await
+        {
+            Ok(_) | Err(ResponseError::Rpc(_)) => Ok(start.elapsed()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Fetch the next request ID. By default, IDs count up from `0`, wrapping on overflow.
+    fn next_request_id(&self) -> rpc::RequestId {
+        // the counter only needs to hand out unique values, which every atomic
+        // read-modify-write guarantees regardless of ordering, and no other memory
+        // is synchronized through it
+        rpc::RequestId::Int(self.request_id.fetch_add(self.id_step, Ordering::Relaxed))
+    }
+
+    /// Request responses for several calls at once, sent as a single JSON-RPC batch.
+    /// This returns a `BatchAwaiter`, a `Future` that resolves once every call is answered.
+    pub fn request_batch(&self, calls: Vec<(String, Option<Value>)>) -> BatchAwaiter {
+        // an empty batch is invalid JSON-RPC, so don't send anything
+        if calls.is_empty() {
+            return BatchAwaiter {
+                awaiters: vec![],
+                results: vec![],
+                batch_error: None,
+                batch_error_txs: Arc::clone(&self.batch_error_txs),
+            };
+        }
+
+        let mut awaiters = Vec::with_capacity(calls.len());
+        let mut messages = Vec::with_capacity(calls.len());
+        let mut ids = Vec::with_capacity(calls.len());
+        for (method, params) in calls {
+            let id = self.next_request_id();
+            ids.push(id.clone());
+            awaiters.push(Some(self.register_awaiter(id.clone())));
+            let mut message = rpc::Message::request(id, method, params);
+            self.apply_outgoing(&mut message);
+            messages.push(message);
+        }
+
+        // register for an error rejecting the whole batch
+        let (tx, rx) = oneshot::channel::<rpc::Error>();
+        self.batch_error_txs
+            .lock()
+            .unwrap()
+            .push_back(PendingBatch { ids, tx });
+
+        if let Err(error) = self.write_line(&messages) {
+            eprintln!("failed to write to Omegga: {}", error);
+        }
+
+        BatchAwaiter {
+            results: awaiters.iter().map(|_| None).collect(),
+            awaiters,
+            batch_error: Some(rx),
+            batch_error_txs: Arc::clone(&self.batch_error_txs),
+        }
+    }
+
+    /// Request a response from the RPC server using an explicit request ID,
+    /// such as a `RequestId::Str`.
+    ///
+    /// The ID must not collide with another pending request: doing so replaces
+    /// the older awaiter, which then resolves to a receive error.
+    pub fn request_with_id(
+        &self,
+        id: impl Into<rpc::RequestId>,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> ResponseAwaiter {
+        let id = id.into();
+
+        // register before writing, so a fast response can't arrive before
+        // anyone is listening for it
+        let awaiter = self.register_awaiter(id.clone());
 
         // write out the request
-        self.write_request(rpc::RequestId::Int(id), method, params);
+        self.write_request(id, method, params);
+
+        awaiter
+    }
+
+    /// Write a fully formed request and await its response, for building requests by hand.
+    ///
+    /// Returns an error without writing anything if `message` isn't a request.
+    pub fn send_and_await(&self, message: rpc::Message) -> Result<ResponseAwaiter, WriteError> {
+        let id = match &message {
+            rpc::Message::Request { id, .. } => id.clone(),
+            _ => return Err(WriteError::InvalidArgument("message must be a request")),
+        };
+
+        let awaiter = self.register_awaiter(id);
+        self.write(message);
+        Ok(awaiter)
+    }
+
+    /// Await whichever of several requests is answered first, returning its index
+    /// in `awaiters` and its result.
+    ///
+    /// The other awaiters are dropped, removing their pending entries, so their
+    /// responses are ignored if they arrive later.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `awaiters` is empty, since there would be nothing to wait for.
+    pub async fn race(
+        mut awaiters: Vec<ResponseAwaiter>,
+    ) -> (usize, Result<Option<Value>, ResponseError>) {
+        assert!(
+            !awaiters.is_empty(),
+            "cannot race an empty list of awaiters"
+        );
+
+        std::future::poll_fn(|cx| {
+            for (i, awaiter) in awaiters.iter_mut().enumerate() {
+                if let Poll::Ready(result) = Pin::new(awaiter).poll(cx) {
+                    return Poll::Ready((i, result));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Stop waiting for a request, so its [`ResponseAwaiter`] resolves to
+    /// [`ResponseError::Recv`](ResponseError::Recv). A response arriving after is treated
+    /// as an orphan. Returns whether the request was still waiting.
+    pub fn cancel_request(&self, id: &rpc::RequestId) -> bool {
+        self.awaiter_txs.remove(id).is_some()
+    }
+
+    /// The number of requests still awaiting a response.
+    pub fn pending_requests(&self) -> usize {
+        self.awaiter_txs.len()
+    }
+
+    /// The IDs of the requests still awaiting a response, in no particular order.
+    pub fn pending_ids(&self) -> Vec<rpc::RequestId> {
+        self.awaiter_txs
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Request responses for several calls at once like [`request_batch`](Omegga::request_batch),
+    /// taking whatever has arrived once `timeout` elapses. Calls still unanswered by then
+    /// resolve to [`ResponseError::Timeout`](ResponseError::Timeout).
+    pub async fn request_all_timeout(
+        &self,
+        calls: Vec<(String, Option<Value>)>,
+        timeout: Duration,
+    ) -> Vec<Result<Option<Value>, ResponseError>> {
+        self.request_batch(calls).with_timeout(timeout).await
+    }
 
+    /// Register an awaiter for a response to `id`.
+    fn register_awaiter(&self, id: rpc::RequestId) -> ResponseAwaiter {
         // create a channel to send the response over
         let (tx, rx) = oneshot::channel::<rpc::Response>();
 
         // insert the transmitter into the dashmap
-        self.awaiter_txs.insert(rpc::RequestId::Int(id), tx);
+        self.awaiter_txs.insert(id.clone(), tx);
 
         // return back with an awaiter to await the receiver
-        ResponseAwaiter(rx)
+        ResponseAwaiter {
+            rx,
+            id,
+            awaiter_txs: Arc::clone(&self.awaiter_txs),
+            completed: false,
+            sent: Instant::now(),
+            timeout: self.request_timeout,
+            deadline: None,
+        }
     }
 
     /// Register commands with Omegga. Call when the plugin is initialized.
+    #[deprecated(note = "use `Omegga::respond_init` instead")]
     pub fn register_commands(&self, id: rpc::RequestId, commands: &[&str]) {
-        self.write_response(id, Some(json!({ "registeredCommands": commands })), None);
+        self.respond_init(id, commands.iter().copied());
+    }
+
+    /// Respond to Omegga's `init` request, registering the plugin's commands.
+    /// Omegga waits for this response before considering the plugin started.
+    pub fn respond_init<I>(&self, id: rpc::RequestId, commands: I)
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let commands = commands
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<String>>();
+        self.write_response_ok(id, Some(json!({ "registeredCommands": commands })));
+    }
+
+    /// Respond to Omegga's `init` request, registering every command in `router`.
+    pub fn respond_init_from_router(&self, id: rpc::RequestId, router: &commands::CommandRouter) {
+        self.respond_init(id, router.command_names());
+    }
+
+    /// Respond to Omegga's `stop` request. Omegga waits for this response before
+    /// stopping the plugin.
+    pub fn respond_stop(&self, id: rpc::RequestId) {
+        self.write_response_ok(id, None);
     }
 
     /// Prints a message to the Omegga console.
@@ -395,28 +1149,41 @@ impl Omegga {
         self.write_notification("trace", Some(Value::String(line.into())));
     }
 
+    /// Gets a handle to the plugin's persistent key-value store.
+    pub fn store(&self) -> store::Store<'_> {
+        store::Store::new(self)
+    }
+
     /// Gets an object from the store.
+    #[deprecated(note = "use `Omegga::store` instead")]
     pub async fn store_get(&self, key: impl Into<String>) -> Result<Option<Value>, ResponseError> {
         self.request("store.get", Some(Value::String(key.into())))
             .await
     }
 
     /// Sets an object in the store.
+    #[deprecated(note = "use `Omegga::store` instead")]
     pub fn store_set(&self, key: impl Into<String>, value: Value) {
-        self.write_notification("store.set", Some(json!([key.into(), value])))
+        self.write_notification(
+            "store.set",
+            rpc::Params::positional(vec![key.into().into(), value]).into(),
+        )
     }
 
     /// Deletes an object from the store.
+    #[deprecated(note = "use `Omegga::store` instead")]
     pub async fn store_delete(&self, key: impl Into<String>) {
         self.write_notification("store.delete", Some(Value::String(key.into())))
     }
 
     /// Wipes the store.
+    #[deprecated(note = "use `Omegga::store` instead")]
     pub fn store_wipe(&self) {
         self.write_notification("store.wipe", None)
     }
 
     /// Gets a list of keys in the store.
+    #[deprecated(note = "use `Omegga::store` instead")]
     pub async fn store_keys(&self) -> Result<Vec<String>, ResponseError> {
         self.request("store.keys", None).await.map(|r| match r {
             Some(r) => serde_json::from_value::<Vec<String>>(r).unwrap_or_else(|_| vec![]),
@@ -429,56 +1196,442 @@ impl Omegga {
         self.write_notification("exec", Some(Value::String(line.into())));
     }
 
+    /// Runs a console command on the Brickadia server, like `Chat.Command /Kick "name"`.
+    ///
+    /// Omegga doesn't tie the server's output to the command that caused it, so there's no
+    /// response to await; watch for [`Event::Line`](Event::Line) with
+    /// [`subscribe("line")`](Omegga::subscribe) instead.
+    ///
+    /// Take care when interpolating untrusted input like player names into the command: it
+    /// could close a quoted argument early and add arguments of its own. Returns an error
+    /// without writing anything if the command is empty or spans multiple lines.
+    pub fn console_command(&self, command: impl Into<String>) -> Result<(), WriteError> {
+        let command = command.into();
+        if command.trim().is_empty() {
+            return Err(WriteError::InvalidArgument("command must not be empty"));
+        }
+        if command.contains(['\n', '\r']) {
+            return Err(WriteError::InvalidArgument("command must be a single line"));
+        }
+
+        self.writeln(command);
+        Ok(())
+    }
+
     /// Broadcasts a line.
     pub fn broadcast(&self, line: impl Into<String>) {
-        self.write_notification("broadcast", Some(Value::String(line.into())));
+        self.write_chat("broadcast", Value::String(line.into()));
     }
 
-    /// Whispers a line to a user by their name.
-    pub fn whisper(&self, username: impl Into<String>, line: impl Into<String>) {
-        self.write_notification(
-            "whisper",
-            Some(json!({"target": username.into(), "line": line.into()})),
-        );
+    /// Broadcasts a line, escaping any chat markup in it.
+    ///
+    /// Use this for untrusted text such as player input. See [`resources::escape`](resources::escape)
+    /// for the list of escaped characters.
+    pub fn broadcast_escaped(&self, line: impl AsRef<str>) {
+        self.broadcast(resources::escape(line.as_ref()));
+    }
+
+    /// Whispers a line to a user by their name or ID.
+    ///
+    /// Returns an error without writing anything if the target is empty.
+    pub fn whisper(
+        &self,
+        target: impl Into<String>,
+        line: impl Into<String>,
+    ) -> Result<(), WriteError> {
+        let target = target.into();
+        if target.is_empty() {
+            return Err(WriteError::InvalidArgument(
+                "whisper target must not be empty",
+            ));
+        }
+
+        self.write_chat("whisper", json!({"target": target, "line": line.into()}));
+        Ok(())
+    }
+
+    /// Whispers the same line to several users by their names or IDs.
+    ///
+    /// Returns an error without writing anything if any target is empty.
+    pub fn whisper_many(
+        &self,
+        targets: &[String],
+        line: impl Into<String>,
+    ) -> Result<(), WriteError> {
+        if targets.iter().any(String::is_empty) {
+            return Err(WriteError::InvalidArgument(
+                "whisper target must not be empty",
+            ));
+        }
+
+        let line = line.into();
+        for target in targets {
+            self.whisper(target, line.clone())?;
+        }
+        Ok(())
     }
 
     pub fn middle_print(&self, username: impl Into<String>, line: impl Into<String>) {
-        self.write_notification(
+        self.write_chat(
             "middlePrint",
-            Some(json!({"target": username.into(), "line": line.into()})),
+            json!({"target": username.into(), "line": line.into()}),
         );
     }
 
+    /// Write a chat notification, subject to the [chat rate](OmeggaBuilder::chat_rate).
+    fn write_chat(&self, method: &'static str, params: Value) {
+        let limiter = match &self.chat_limiter {
+            Some(limiter) => limiter,
+            None => return self.write_notification(method, Some(params)),
+        };
+
+        match limiter.acquire() {
+            Some(Duration::ZERO) => self.write_notification(method, Some(params)),
+            Some(delay) => {
+                let omegga = self.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    omegga.write_notification(method, Some(params));
+                });
+            }
+            None => (),
+        }
+    }
+
+    /// The number of chat messages dropped for going over the
+    /// [chat rate](OmeggaBuilder::chat_rate) with [`Overflow::Drop`](ratelimit::Overflow::Drop).
+    pub fn dropped_chat(&self) -> u64 {
+        self.chat_limiter
+            .as_ref()
+            .map_or(0, |limiter| limiter.dropped())
+    }
+
     /// Gets a list of all players.
+    ///
+    /// A malformed player list is surfaced as [`ResponseError::Deserialize`](ResponseError::Deserialize)
+    /// instead of being treated as an empty server.
     pub async fn get_players(&self) -> Result<Vec<Player>, ResponseError> {
-        self.request("getPlayers", None).await.map(|r| match r {
-            Some(r) => serde_json::from_value::<Vec<Player>>(r).unwrap_or_else(|_| vec![]),
-            None => vec![],
-        })
+        match self.request("getPlayers", None).await? {
+            Some(r) => Ok(serde_json::from_value::<Vec<Player>>(r)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Gets all players, grouped by name.
+    ///
+    /// Names are matched exactly, case and all. Brickadia allows several players to share
+    /// a display name, so each name maps to every player using it, in the order Omegga
+    /// listed them.
+    pub async fn players_by_name(&self) -> Result<HashMap<String, Vec<Player>>, ResponseError> {
+        let mut players = HashMap::<String, Vec<Player>>::new();
+        for player in self.get_players().await? {
+            players.entry(player.name.clone()).or_default().push(player);
+        }
+        Ok(players)
+    }
+
+    /// Gets all players, keyed by their unique ID.
+    pub async fn players_by_id(&self) -> Result<HashMap<String, Player>, ResponseError> {
+        Ok(self
+            .get_players()
+            .await?
+            .into_iter()
+            .map(|player| (player.id.clone(), player))
+            .collect())
+    }
+
+    /// Get the number of online players.
+    ///
+    /// Omegga has no lighter call than `getPlayers`, so this still costs a round trip
+    /// and the response is still parsed as JSON, but the players aren't deserialized.
+    pub async fn player_count(&self) -> Result<usize, ResponseError> {
+        match self.request("getPlayers", None).await? {
+            Some(Value::Array(players)) => Ok(players.len()),
+            None | Some(Value::Null) => Ok(0),
+            Some(_) => Err(ResponseError::Deserialize(serde::de::Error::custom(
+                "expected an array of players",
+            ))),
+        }
+    }
+
+    /// Get all player positions.
+    pub async fn get_all_player_positions(&self) -> Result<Vec<PlayerPosition>, ResponseError> {
+        match self.request("getAllPlayerPositions", None).await? {
+            Some(r) => Ok(serde_json::from_value::<Vec<PlayerPosition>>(r)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Get all player positions, keyed by player ID.
+    /// Players without a position (e.g. dead or still loading) map to `None`.
+    pub async fn get_all_positions(
+        &self,
+    ) -> Result<HashMap<String, Option<Position>>, ResponseError> {
+        Ok(self
+            .get_all_player_positions()
+            .await?
+            .into_iter()
+            .map(|p| (p.player.id, p.pos.map(Position::from)))
+            .collect())
+    }
+
+    /// Gets the minigames running on the server.
+    pub async fn get_minigames(&self) -> Result<Vec<Minigame>, ResponseError> {
+        match self.request("getMinigames", None).await? {
+            Some(r) => Ok(serde_json::from_value::<Vec<Minigame>>(r)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Reset the minigame at `index`, as in [`Minigame::index`](resources::Minigame::index).
+    ///
+    /// Brickadia's console can reset, advance and delete minigames, but has no command to
+    /// add or remove members, so players join minigames themselves.
+    pub fn reset_minigame(&self, index: i32) {
+        self.writeln(format!("Server.Minigames.Reset {}", index));
+    }
+
+    /// Start the next round of the minigame at `index`.
+    pub fn next_round_minigame(&self, index: i32) {
+        self.writeln(format!("Server.Minigames.NextRound {}", index));
+    }
+
+    /// Delete the minigame at `index`, moving its members back to the server.
+    pub fn delete_minigame(&self, index: i32) {
+        self.writeln(format!("Server.Minigames.Delete {}", index));
+    }
+
+    /// Gets the players within `radius` of `center`, inclusive, in Unreal units.
+    ///
+    /// The distance is measured in 3D, Z being vertical, so a player high above the
+    /// center can be out of range. Players without a known position, like dead ones,
+    /// are left out.
+    pub async fn players_in_radius(
+        &self,
+        center: impl Into<Position>,
+        radius: f64,
+    ) -> Result<Vec<Player>, ResponseError> {
+        let center = center.into();
+        Ok(self
+            .get_all_player_positions()
+            .await?
+            .into_iter()
+            .filter(|p| {
+                p.pos
+                    .is_some_and(|pos| Position::from(pos).distance(&center) <= radius)
+            })
+            .map(|p| p.player)
+            .collect())
+    }
+
+    /// Get the role setup.
+    pub async fn get_role_setup(&self) -> Result<RoleSetup, ResponseError> {
+        self.request_typed("getRoleSetup", None).await
+    }
+
+    /// Get the permissions a role sets, mapped to whether they're allowed, or `None` for
+    /// an unknown role. See [`Role::permission_map`](resources::Role::permission_map).
+    pub async fn get_role_permissions(
+        &self,
+        role: &str,
+    ) -> Result<Option<HashMap<String, bool>>, ResponseError> {
+        Ok(self
+            .get_role_setup()
+            .await?
+            .role(role)
+            .map(resources::Role::permission_map))
+    }
+
+    /// Get the server's status, including its online players.
+    pub async fn get_server_status(&self) -> Result<ServerStatus, ResponseError> {
+        self.request_typed("getServerStatus", None).await
+    }
+
+    /// Get the server's current environment preset.
+    pub async fn get_environment_data(&self) -> Result<Environment, ResponseError> {
+        self.request_typed("getEnvironmentData", None).await
+    }
+
+    /// Load an environment preset, like one read with
+    /// [`get_environment_data`](Omegga::get_environment_data) and then changed.
+    pub async fn set_environment_data(
+        &self,
+        environment: &Environment,
+    ) -> Result<(), ResponseError> {
+        self.request(
+            "loadEnvironmentData",
+            Some(serde_json::to_value(environment)?),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Get the in-game time of day, in hours from `0.0` to `24.0`, or `None` if the
+    /// environment doesn't include it.
+    pub async fn get_time_of_day(&self) -> Result<Option<f64>, ResponseError> {
+        Ok(self
+            .get_environment_data()
+            .await?
+            .data
+            .groups
+            .sky
+            .and_then(|sky| sky.time_of_day))
     }
 
-    /// Get all player positions.
-    pub async fn get_all_player_positions(&self) -> Result<Vec<PlayerPosition>, ResponseError> {
-        self.request("getAllPlayerPositions", None)
-            .await
-            .map(|r| match r {
-                Some(r) => {
-                    serde_json::from_value::<Vec<PlayerPosition>>(r).unwrap_or_else(|_| vec![])
-                }
-                None => vec![],
-            })
+    /// Set the in-game time of day, in hours from `0.0` to `24.0`, leaving the rest of
+    /// the environment as it is.
+    ///
+    /// Returns an error without changing anything if `hours` is out of range.
+    pub async fn set_time_of_day(&self, hours: f64) -> Result<(), ResponseError> {
+        if !(0.0..=24.0).contains(&hours) {
+            return Err(
+                WriteError::InvalidArgument("time of day must be from 0 to 24 hours").into(),
+            );
+        }
+
+        let mut environment = self.get_environment_data().await?;
+        environment
+            .data
+            .groups
+            .sky
+            .get_or_insert_with(Default::default)
+            .time_of_day = Some(hours);
+        self.set_environment_data(&environment).await
+    }
+
+    /// Get the ban list, in no particular order.
+    pub async fn get_ban_list(&self) -> Result<Vec<BanEntry>, ResponseError> {
+        #[derive(serde::Deserialize)]
+        struct BanList {
+            #[serde(rename = "banList", default)]
+            ban_list: HashMap<String, Ban>,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct Ban {
+            #[serde(rename = "bannerId")]
+            banner_id: Option<String>,
+            #[serde(default)]
+            reason: String,
+            created: Option<String>,
+            expires: Option<String>,
+        }
+
+        let list: BanList = self.request_typed("getBanList", None).await?;
+        Ok(list
+            .ban_list
+            .into_iter()
+            .map(|(id, ban)| BanEntry {
+                id,
+                banner_id: ban.banner_id,
+                reason: ban.reason,
+                banned_at: ban.created,
+                expires_at: ban.expires.filter(|expires| !expires.is_empty()),
+            })
+            .collect())
+    }
+
+    /// Unban a player by their name or ID.
+    ///
+    /// Returns an error without writing anything if the target is empty or contains a
+    /// double quote.
+    pub fn unban_player(&self, target: impl AsRef<str>) -> Result<(), WriteError> {
+        let target = moderation_target(target.as_ref())?;
+        self.console_command(format!("Chat.Command /Unban {}", target))
+    }
+
+    /// Grant a role to a player by their name or ID, failing with
+    /// [`ResponseError::RoleNotFound`](ResponseError::RoleNotFound) if the server has no such role.
+    ///
+    /// Roles are granted through the server console, which doesn't answer, so success only
+    /// means the role exists and the command was sent.
+    pub async fn add_role(
+        &self,
+        target: impl AsRef<str>,
+        role: impl AsRef<str>,
+    ) -> Result<(), ResponseError> {
+        let command = self
+            .role_command("GrantRole", target.as_ref(), role.as_ref())
+            .await?;
+        Ok(self.console_command(command)?)
+    }
+
+    /// Revoke a role from a player by their name or ID, failing with
+    /// [`ResponseError::RoleNotFound`](ResponseError::RoleNotFound) if the server has no such role.
+    ///
+    /// Like [`add_role`](Omegga::add_role), success only means the command was sent.
+    pub async fn remove_role(
+        &self,
+        target: impl AsRef<str>,
+        role: impl AsRef<str>,
+    ) -> Result<(), ResponseError> {
+        let command = self
+            .role_command("RevokeRole", target.as_ref(), role.as_ref())
+            .await?;
+        Ok(self.console_command(command)?)
+    }
+
+    /// Build a role console command, checking the role exists before anything is sent.
+    async fn role_command(
+        &self,
+        command: &str,
+        target: &str,
+        role: &str,
+    ) -> Result<String, ResponseError> {
+        let target = moderation_target(target)?;
+        let quoted_role = quote_argument(role, "role must not contain quotes")?;
+        if self.get_role_setup().await?.role(role).is_none() {
+            return Err(ResponseError::RoleNotFound(role.to_string()));
+        }
+
+        Ok(format!(
+            "Chat.Command /{} {} {}",
+            command, quoted_role, target
+        ))
     }
 
-    /// Get the role setup.
-    pub async fn get_role_setup(&self) -> Result<Value, ResponseError> {
-        // TODO: write a type for this instead of using a serde_json::Value
-        self.request("getRoleSetup", None).await.map(Option::unwrap)
+    /// Kick a player by their name, optionally giving a reason.
+    ///
+    /// Returns an error without writing anything if the name is empty, or if the name
+    /// or reason contains a double quote.
+    pub fn kick_player(
+        &self,
+        target: impl AsRef<str>,
+        reason: Option<String>,
+    ) -> Result<(), WriteError> {
+        let target = moderation_target(target.as_ref())?;
+        let reason = quote_argument(
+            reason.as_deref().unwrap_or(""),
+            "reason must not contain quotes",
+        )?;
+        self.console_command(format!("Chat.Command /Kick {} {}", target, reason))
     }
 
-    /// Get the ban list.
-    pub async fn get_ban_list(&self) -> Result<Value, ResponseError> {
-        // TODO: write a type for this instead of using a serde_json::Value
-        self.request("getBanList", None).await.map(Option::unwrap)
+    /// Ban a player by their name, optionally giving a reason. A ban without a duration
+    /// is permanent.
+    ///
+    /// Brickadia bans in whole minutes, so the duration is rounded up to the next minute.
+    /// Returns an error without writing anything if the name is empty, or if the name
+    /// or reason contains a double quote.
+    pub fn ban_player(
+        &self,
+        target: impl AsRef<str>,
+        reason: Option<String>,
+        duration: Option<Duration>,
+    ) -> Result<(), WriteError> {
+        let target = moderation_target(target.as_ref())?;
+        let reason = quote_argument(
+            reason.as_deref().unwrap_or(""),
+            "reason must not contain quotes",
+        )?;
+        let minutes = match duration {
+            Some(duration) => duration.as_secs().div_ceil(60).max(1) as i64,
+            None => -1,
+        };
+        self.console_command(format!(
+            "Chat.Command /Ban {} {} {}",
+            target, minutes, reason
+        ))
     }
 
     /// Get a list of the server's saves.
@@ -505,18 +1658,22 @@ impl Omegga {
     /// Gets the server's current save data.
     #[cfg(not(feature = "brs"))]
     pub async fn get_save_data(&self) -> Result<Value, ResponseError> {
-        self.request("getSaveData", None).await.map(Option::unwrap)
+        self.request_typed("getSaveData", None).await
     }
 
     /// Gets the server's current save data as a brickadia-rs save object.
+    ///
+    /// Save data that doesn't match the brickadia-rs model is reported as
+    /// [`ResponseError::Deserialize`](ResponseError::Deserialize).
     #[cfg(feature = "brs")]
     pub async fn get_save_data(&self) -> Result<save::SaveData, ResponseError> {
-        self.request("getSaveData", None)
-            .await
-            .map(|r| serde_json::from_value::<save::SaveData>(r.unwrap()).unwrap())
+        self.request_typed("getSaveData", None).await
     }
 
-    /// Clears a player's bricks by their name.
+    /// Clears a player's bricks.
+    ///
+    /// The target may be a player's name or ID. Names only resolve for players
+    /// who are online, so use the ID to clear the bricks of a player who left.
     pub fn clear_bricks(&self, target: impl Into<String>, quiet: bool) {
         self.write_notification(
             "clearBricks",
@@ -524,7 +1681,7 @@ impl Omegga {
         );
     }
 
-    /// Clear all bricks.
+    /// Clear all bricks. When `quiet` is set, players aren't notified in chat.
     pub fn clear_all_bricks(&self, quiet: bool) {
         self.write_notification("clearAllBricks", Some(json!({ "quiet": quiet })));
     }
@@ -536,6 +1693,36 @@ impl Omegga {
             .map(|_| ())
     }
 
+    /// Save the bricks selected by `target` to a named save.
+    ///
+    /// Resolves once the save has been written. Returns an error without sending anything
+    /// if the name is empty. Saving only one player's bricks isn't supported, as Omegga
+    /// can't select bricks by owner.
+    pub async fn save_bricks_with(
+        &self,
+        name: impl Into<String>,
+        target: SaveTarget,
+    ) -> Result<(), ResponseError> {
+        let name = name.into();
+        if name.is_empty() {
+            return Err(WriteError::InvalidArgument("save name must not be empty").into());
+        }
+
+        match target {
+            SaveTarget::All => self.save_bricks(name).await,
+            SaveTarget::Region(region) => {
+                // Omegga can only read a region, so read it and write it out
+                let data = self
+                    .request("getSaveData", Some(serde_json::to_value(region)?))
+                    .await?
+                    .ok_or(ResponseError::NullResult)?;
+                self.request("writeSaveData", Some(json!({"name": name, "data": data})))
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+
     /// Load a save, provided an offset in the world.
     pub async fn load_bricks(
         &self,
@@ -543,7 +1730,34 @@ impl Omegga {
         quiet: bool,
         offset: (i32, i32, i32),
     ) -> Result<(), ResponseError> {
-        self.request("loadBricks", Some(json!({"name": name.into(), "quiet": quiet, "offX": offset.0, "offY": offset.1, "offZ": offset.2}))).await.map(|_| ())
+        let options = LoadOptions {
+            offset,
+            quiet,
+            ..LoadOptions::default()
+        };
+        self.load_bricks_with(name, options).await
+    }
+
+    /// Load a save with the given placement options.
+    pub async fn load_bricks_with(
+        &self,
+        name: impl Into<String>,
+        options: LoadOptions,
+    ) -> Result<(), ResponseError> {
+        self.request(
+            "loadBricks",
+            Some(json!({
+                "name": name.into(),
+                "offX": options.offset.0,
+                "offY": options.offset.1,
+                "offZ": options.offset.2,
+                "quiet": options.quiet,
+                "correctPalette": options.correct_palette,
+                "correctCustom": options.correct_custom,
+            })),
+        )
+        .await
+        .map(|_| ())
     }
 
     /// Load a save onto a player's clipboard.
@@ -580,6 +1794,24 @@ impl Omegga {
             })
     }
 
+    /// Reads a save and computes the bounds of its bricks, or `None` if the save doesn't
+    /// exist or has no bricks.
+    ///
+    /// Omegga has no call for a save's bounds, so the whole save is read. For a player's
+    /// selection, convert the result of [`get_player_template_bounds`](Omegga::get_player_template_bounds)
+    /// into a [`BoundingBox`](resources::BoundingBox) instead.
+    #[cfg(feature = "brs")]
+    pub async fn get_save_bounds(
+        &self,
+        name: impl Into<String>,
+    ) -> Result<Option<resources::BoundingBox>, ResponseError> {
+        Ok(self
+            .read_save_data(name)
+            .await?
+            .as_ref()
+            .and_then(resources::BoundingBox::from_save))
+    }
+
     /// Loads a save (from a JSON value) into the world, provided an offset.
     #[cfg(not(feature = "brs"))]
     pub async fn load_save_data(
@@ -625,20 +1857,36 @@ impl Omegga {
     }
 
     /// Changes the map.
+    ///
+    /// Omegga only responds once the new map has loaded, so this resolves after the load
+    /// rather than immediately. The listener also receives an [`Event::MapChange`](Event::MapChange)
+    /// once the map is ready. Returns an error without sending anything if the map name is empty.
     pub async fn change_map(&self, map: impl Into<String>) -> Result<(), ResponseError> {
-        self.request("changeMap", Some(Value::String(map.into())))
+        let map = map.into();
+        if map.is_empty() {
+            return Err(WriteError::InvalidArgument("map name must not be empty").into());
+        }
+
+        self.request("changeMap", Some(Value::String(map)))
             .await
             .map(|_| ())
     }
 
-    /// Get a player.
+    /// Get an online player by their name or ID.
+    ///
+    /// This asks Omegga for the one player rather than fetching the whole player list.
+    /// A player who isn't online, such as one who just left, is `Ok(None)`.
     pub async fn get_player(
         &self,
         target: impl Into<String>,
     ) -> Result<Option<Player>, ResponseError> {
-        self.request("player.get", Some(Value::String(target.into())))
-            .await
-            .map(|r| serde_json::from_value::<Player>(r.unwrap_or(Value::Null)).ok())
+        match self
+            .request("player.get", Some(Value::String(target.into())))
+            .await?
+        {
+            None | Some(Value::Null) => Ok(None),
+            Some(player) => Ok(Some(serde_json::from_value(player)?)),
+        }
     }
 
     /// Get a player's roles.
@@ -661,6 +1909,21 @@ impl Omegga {
             .map(|r| r.unwrap_or(Value::Null))
     }
 
+    /// Check whether a player has a permission, such as `Bricks.ClearAll`.
+    /// Permissions the player's roles don't mention count as not granted.
+    pub async fn has_permission(
+        &self,
+        target: impl Into<String>,
+        permission: &str,
+    ) -> Result<bool, ResponseError> {
+        Ok(self
+            .get_player_permissions(target)
+            .await?
+            .get(permission)
+            .and_then(Value::as_bool)
+            .unwrap_or(false))
+    }
+
     /// Get a player's name color (6-digit hexadecimal).
     pub async fn get_player_name_color(
         &self,
@@ -671,6 +1934,22 @@ impl Omegga {
             .map(|r| r.and_then(|r| serde_json::from_value::<_>(r).ok()))
     }
 
+    /// Get a player's name color and paint at once, or `None` if Omegga has neither for them.
+    pub async fn get_player_appearance(
+        &self,
+        target: impl Into<String>,
+    ) -> Result<Option<PlayerAppearance>, ResponseError> {
+        let target = target.into();
+        let (name_color, paint) = tokio::join!(
+            self.get_player_name_color(target.clone()),
+            self.get_player_paint(target)
+        );
+        Ok(match (name_color?, paint?) {
+            (None, None) => None,
+            (name_color, paint) => Some(PlayerAppearance { name_color, paint }),
+        })
+    }
+
     /// Get a player's position.
     pub async fn get_player_position(
         &self,
@@ -774,37 +2053,114 @@ impl Omegga {
             .map(|_| ())
     }
 
-    /// Get a plugin.
+    /// Get a plugin by name, or `None` if there's no such plugin.
+    ///
+    /// A plugin that doesn't match [`Plugin`] fails with
+    /// [`ResponseError::Deserialize`](ResponseError::Deserialize) rather than being reported
+    /// as missing.
     pub async fn get_plugin(
         &self,
         target: impl Into<String>,
     ) -> Result<Option<Plugin>, ResponseError> {
-        self.request("plugin.get", Some(Value::String(target.into())))
-            .await
-            .map(|r| r.and_then(|r| serde_json::from_value::<_>(r).ok()))
+        let result = self
+            .request("plugin.get", Some(Value::String(target.into())))
+            .await?;
+        plugin_from(result)
+    }
+
+    /// Get several plugins by name in a single batch, leaving out any that aren't loaded.
+    ///
+    /// Omegga can only look plugins up by name, not list them, so the names have to be
+    /// known ahead of time. An error for any lookup, such as a permission error, fails the
+    /// whole call rather than being reported as a missing plugin.
+    pub async fn get_plugins<I>(&self, names: I) -> Result<Vec<Plugin>, ResponseError>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let calls = names
+            .into_iter()
+            .map(|name| ("plugin.get".to_string(), Some(Value::String(name.into()))))
+            .collect();
+
+        let mut plugins = vec![];
+        for result in self.request_batch(calls).await {
+            plugins.extend(plugin_from(result?)?);
+        }
+        Ok(plugins)
     }
 
-    /// Emit a custom event to a plugin.
+    /// Emit a custom event to a plugin, deserializing what its handler returns.
+    ///
+    /// A missing or `null` return value is `Ok(None)`. Omegga doesn't distinguish a
+    /// missing plugin from one that returned nothing, so use
+    /// [`emit_plugin_checked`](Omegga::emit_plugin_checked) to tell them apart.
     pub async fn emit_plugin<T>(
         &self,
-        target: String,
-        event: String,
+        target: impl Into<String>,
+        event: impl Into<String>,
         args: Vec<Value>,
     ) -> Result<Option<T>, ResponseError>
     where
-        T: serde::de::DeserializeOwned,
+        T: DeserializeOwned,
     {
         let mut query = vec![Value::String(target.into()), Value::String(event.into())];
-        query.extend(args.into_iter());
+        query.extend(args);
+
+        match self
+            .request("plugin.emit", rpc::Params::positional(query).into())
+            .await?
+        {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+        }
+    }
 
-        self.request("plugin.emit", Some(Value::Array(query)))
-            .await
-            .map(|r| {
-                serde_json::from_value::<_>(r.unwrap_or_default())
-                    .ok()
-                    .unwrap_or_default()
-            })
+    /// Emit a custom event to a plugin like [`emit_plugin`](Omegga::emit_plugin), first
+    /// checking that the plugin exists, failing with
+    /// [`ResponseError::PluginNotFound`](ResponseError::PluginNotFound) if it doesn't.
+    pub async fn emit_plugin_checked<T>(
+        &self,
+        target: impl Into<String>,
+        event: impl Into<String>,
+        args: Vec<Value>,
+    ) -> Result<Option<T>, ResponseError>
+    where
+        T: DeserializeOwned,
+    {
+        let target = target.into();
+        if self.get_plugin(target.as_str()).await?.is_none() {
+            return Err(ResponseError::PluginNotFound(target));
+        }
+
+        self.emit_plugin(target, event, args).await
+    }
+}
+
+/// Read a `plugin.get` result, which is `null` (or `false` on some Omegga versions)
+/// when there's no such plugin.
+fn plugin_from(result: Option<Value>) -> Result<Option<Plugin>, ResponseError> {
+    match result {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => Ok(None),
+        Some(plugin) => Ok(Some(serde_json::from_value(plugin)?)),
+    }
+}
+
+/// Validate and quote the player name passed to a moderation command.
+fn moderation_target(target: &str) -> Result<String, WriteError> {
+    if target.is_empty() {
+        return Err(WriteError::InvalidArgument("target must not be empty"));
     }
+    quote_argument(target, "target must not contain quotes")
+}
+
+/// Quote an argument for a console command, failing with `error` if it contains a
+/// quote that would end it early.
+fn quote_argument(argument: &str, error: &'static str) -> Result<String, WriteError> {
+    if argument.contains('"') {
+        return Err(WriteError::InvalidArgument(error));
+    }
+    Ok(format!("\"{}\"", argument))
 }
 
 impl Default for Omegga {
@@ -812,3 +2168,903 @@ impl Default for Omegga {
         Self::new()
     }
 }
+
+/// A builder for an [`Omegga`](Omegga) instance.
+pub struct OmeggaBuilder {
+    starting_id: i32,
+    id_step: i32,
+    forward_orphans: bool,
+    shutdown_timeout: Duration,
+    chat_rate: Option<u32>,
+    chat_overflow: ratelimit::Overflow,
+    request_timeout: Option<Duration>,
+    output: Box<dyn Write + Send>,
+    outgoing: Vec<Middleware>,
+    incoming: Vec<Middleware>,
+}
+
+impl OmeggaBuilder {
+    /// Create a new builder with the default settings.
+    pub fn new() -> Self {
+        Self {
+            starting_id: 0,
+            id_step: 1,
+            forward_orphans: false,
+            shutdown_timeout: Duration::from_secs(5),
+            chat_rate: None,
+            chat_overflow: ratelimit::Overflow::Delay,
+            request_timeout: None,
+            output: Box::new(io::stdout()),
+            outgoing: vec![],
+            incoming: vec![],
+        }
+    }
+
+    /// Set the ID of the first request. Defaults to `0`.
+    pub fn starting_id(mut self, id: i32) -> Self {
+        self.starting_id = id;
+        self
+    }
+
+    /// Set the amount added to the request ID after each request. Defaults to `1`.
+    ///
+    /// A step of `2` with an odd starting ID, for example, keeps IDs from colliding
+    /// with another component allocating even IDs on the same stream.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is `0`, which would give every request the same ID.
+    pub fn id_step(mut self, step: i32) -> Self {
+        assert_ne!(step, 0, "the request ID step must not be 0");
+        self.id_step = step;
+        self
+    }
+
+    /// Set whether responses with no request waiting for them, like ones arriving after
+    /// their request timed out, are sent to the event channel as
+    /// [`Event::OrphanResponse`](Event::OrphanResponse) instead of being dropped. Defaults to `false`.
+    pub fn forward_orphans(mut self, forward: bool) -> Self {
+        self.forward_orphans = forward;
+        self
+    }
+
+    /// Set how long [`Omegga::run`](Omegga::run) waits for the handlers registered with
+    /// [`Omegga::on_shutdown`](Omegga::on_shutdown) before giving up on them. Defaults to 5 seconds.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Set how long every request waits for its response before failing with
+    /// [`ResponseError::Timeout`](ResponseError::Timeout). Requests wait indefinitely by default.
+    ///
+    /// A timeout passed to [`Omegga::request_with_timeout`](Omegga::request_with_timeout) or
+    /// [`ResponseAwaiter::timeout`](ResponseAwaiter::timeout) overrides this one.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit [`broadcast`](Omegga::broadcast), [`whisper`](Omegga::whisper) and
+    /// [`middle_print`](Omegga::middle_print) to this many messages a second, with
+    /// bursts of up to a second's worth. Unlimited by default.
+    ///
+    /// Messages over the limit are delayed unless set otherwise with
+    /// [`chat_overflow`](OmeggaBuilder::chat_overflow). Delayed messages are sent from a
+    /// spawned task, so chat must then be sent from within a Tokio runtime.
+    pub fn chat_rate(mut self, per_second: u32) -> Self {
+        self.chat_rate = Some(per_second);
+        self
+    }
+
+    /// Set what happens to chat messages over the [chat rate](OmeggaBuilder::chat_rate).
+    /// Defaults to [`Overflow::Delay`](ratelimit::Overflow::Delay).
+    pub fn chat_overflow(mut self, overflow: ratelimit::Overflow) -> Self {
+        self.chat_overflow = overflow;
+        self
+    }
+
+    /// Set where outgoing messages are written, one per line. Defaults to stdout.
+    ///
+    /// Pair with [`Omegga::spawn_from`](Omegga::spawn_from) to run over a transport
+    /// other than stdio, or to capture every emitted message in tests.
+    pub fn writer(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.output = Box::new(writer);
+        self
+    }
+
+    /// Add a hook run on every outgoing message before it's serialized, after any
+    /// hooks added before it.
+    ///
+    /// Hooks may rewrite the message, but changing a request's ID means its
+    /// response will never reach the awaiter.
+    pub fn outgoing(
+        mut self,
+        middleware: impl Fn(&mut rpc::Message) + Send + Sync + 'static,
+    ) -> Self {
+        self.outgoing.push(Box::new(middleware));
+        self
+    }
+
+    /// Add a hook run on every message parsed by the listener before it's routed,
+    /// after any hooks added before it.
+    pub fn incoming(
+        mut self,
+        middleware: impl Fn(&mut rpc::Message) + Send + Sync + 'static,
+    ) -> Self {
+        self.incoming.push(Box::new(middleware));
+        self
+    }
+
+    /// Build the Omegga instance.
+    pub fn build(self) -> Omegga {
+        Omegga {
+            awaiter_txs: Arc::new(DashMap::new()),
+            batch_error_txs: Arc::new(Mutex::new(VecDeque::new())),
+            request_id: Arc::new(AtomicI32::new(self.starting_id)),
+            id_step: self.id_step,
+            shutdown_tx: Arc::new(watch::channel(()).0),
+            output: Arc::new(Mutex::new(self.output)),
+            buffered: Arc::new(Mutex::new(Vec::new())),
+            handlers: Arc::new(RwLock::new(handlers::Handlers::default())),
+            listening: Arc::new(AtomicBool::new(false)),
+            outgoing: self.outgoing.into(),
+            incoming: self.incoming.into(),
+            subscribers: broadcast::channel(SUBSCRIPTION_CAPACITY).0,
+            forward_orphans: self.forward_orphans,
+            shutdown_timeout: self.shutdown_timeout,
+            chat_limiter: self
+                .chat_rate
+                .map(|rate| Arc::new(ratelimit::RateLimiter::new(rate, self.chat_overflow))),
+            init: Arc::new(watch::channel(None).0),
+            request_timeout: self.request_timeout,
+            #[cfg(feature = "metrics")]
+            counters: Arc::new(metrics::Counters::default()),
+        }
+    }
+}
+
+impl Default for OmeggaBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockOmegga, resources::BoundingBox};
+
+    fn omegga() -> Omegga {
+        Omegga::builder().writer(io::sink()).build()
+    }
+
+    #[test]
+    fn dropping_a_replaced_awaiter_keeps_the_new_one() {
+        let omegga = omegga();
+        let old = omegga.request_with_id("reused", "plugin.get", None);
+        let _new = omegga.request_with_id("reused", "plugin.get", None);
+
+        drop(old);
+        assert!(omegga
+            .awaiter_txs
+            .contains_key(&rpc::RequestId::from("reused")));
+    }
+
+    #[tokio::test]
+    async fn looks_up_plugins() {
+        let mock = MockOmegga::new();
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        mock.respond_to(
+            "plugin.get",
+            Some(json!({
+                "name": "other",
+                "documentation": {"name": "other", "description": "", "author": "x"},
+                "loaded": true
+            })),
+        );
+        let plugin = omegga.get_plugin("other").await.unwrap().unwrap();
+        assert!(plugin.loaded);
+        assert_eq!(plugin.documentation.unwrap().author, "x");
+
+        mock.respond_to("plugin.emit", Some(json!("pong")));
+        let reply = omegga.emit_plugin_checked::<String>("other", "ping", vec![]);
+        assert_eq!(reply.await.unwrap().as_deref(), Some("pong"));
+
+        mock.respond_to("plugin.get", Some(json!({"name": 1})));
+        assert!(matches!(
+            omegga.get_plugin("other").await,
+            Err(ResponseError::Deserialize(_))
+        ));
+        assert!(matches!(
+            omegga.get_plugins(["other"]).await,
+            Err(ResponseError::Deserialize(_))
+        ));
+
+        mock.respond_to("plugin.get", None);
+        assert!(matches!(
+            omegga.emit_plugin_checked::<Value>("other", "ping", vec![]).await,
+            Err(ResponseError::PluginNotFound(name)) if name == "other"
+        ));
+    }
+
+    /// The JSON of every message the plugin has sent to the mock, in order.
+    fn sent_json(mock: &MockOmegga) -> Vec<Value> {
+        mock.sent()
+            .iter()
+            .map(|message| serde_json::to_value(message).unwrap())
+            .collect()
+    }
+
+    /// Wait until the plugin has sent `count` messages to the mock.
+    async fn wait_for_count(mock: &MockOmegga, count: usize) {
+        while mock.sent().len() < count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn clears_bricks() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        omegga.clear_bricks("3f5108a0-c929-4e77-a115-21f65096887b", true);
+        omegga.clear_all_bricks(false);
+
+        wait_for_count(&mock, 2).await;
+        assert_eq!(
+            sent_json(&mock),
+            vec![
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "clearBricks",
+                    "params": {"target": "3f5108a0-c929-4e77-a115-21f65096887b", "quiet": true}
+                }),
+                json!({"jsonrpc": "2.0", "method": "clearAllBricks", "params": {"quiet": false}}),
+            ]
+        );
+    }
+
+    /// A console command as the plugin sends it.
+    fn exec(command: &str) -> Value {
+        json!({"jsonrpc": "2.0", "method": "exec", "params": command})
+    }
+
+    #[tokio::test]
+    async fn reads_the_ban_list() {
+        let mock = MockOmegga::new();
+        mock.respond_to(
+            "getBanList",
+            Some(json!({
+                "banList": {
+                    "a1b2c3d4-0000-4000-8000-000000000001": {
+                        "bannerId": "f0e1d2c3-0000-4000-8000-000000000009",
+                        "created": "2022.03.14-20.15.42",
+                        "expires": "2022.03.15-20.15.42",
+                        "reason": "griefing"
+                    },
+                    "a1b2c3d4-0000-4000-8000-000000000002": {
+                        "bannerId": "f0e1d2c3-0000-4000-8000-000000000009",
+                        "created": "2022.03.14-21.00.00",
+                        "expires": "",
+                        "reason": "cheating"
+                    },
+                    "a1b2c3d4-0000-4000-8000-000000000003": {
+                        "created": "2022.03.14-22.00.00"
+                    }
+                }
+            })),
+        );
+        let _events = mock.spawn();
+
+        let mut bans = mock.omegga().get_ban_list().await.unwrap();
+        bans.sort_by(|a, b| a.id.cmp(&b.id));
+        let bans: Vec<_> = bans
+            .iter()
+            .map(|ban| {
+                (
+                    ban.id.as_str(),
+                    ban.banner_id.as_deref(),
+                    ban.reason.as_str(),
+                    ban.banned_at.as_deref(),
+                    ban.expires_at.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            bans,
+            [
+                (
+                    "a1b2c3d4-0000-4000-8000-000000000001",
+                    Some("f0e1d2c3-0000-4000-8000-000000000009"),
+                    "griefing",
+                    Some("2022.03.14-20.15.42"),
+                    Some("2022.03.15-20.15.42"),
+                ),
+                (
+                    "a1b2c3d4-0000-4000-8000-000000000002",
+                    Some("f0e1d2c3-0000-4000-8000-000000000009"),
+                    "cheating",
+                    Some("2022.03.14-21.00.00"),
+                    None,
+                ),
+                (
+                    "a1b2c3d4-0000-4000-8000-000000000003",
+                    None,
+                    "",
+                    Some("2022.03.14-22.00.00"),
+                    None,
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unbans_players() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        omegga.unban_player("x").unwrap();
+        assert!(omegga.unban_player("").is_err());
+
+        wait_for_count(&mock, 1).await;
+        assert_eq!(sent_json(&mock), [exec("Chat.Command /Unban \"x\"")]);
+    }
+
+    #[tokio::test]
+    async fn grants_and_revokes_roles() {
+        let mock = MockOmegga::new();
+        mock.respond_to(
+            "getRoleSetup",
+            Some(json!({"roles": [{"name": "Moderator", "permissions": []}]})),
+        );
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        omegga.add_role("x", "Moderator").await.unwrap();
+        omegga.remove_role("x", "Moderator").await.unwrap();
+        assert!(matches!(
+            omegga.add_role("x", "Nobody").await,
+            Err(ResponseError::RoleNotFound(role)) if role == "Nobody"
+        ));
+
+        wait_for_count(&mock, 5).await;
+        let commands: Vec<_> = sent_json(&mock)
+            .into_iter()
+            .filter(|message| message["method"] == "exec")
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                exec("Chat.Command /GrantRole \"Moderator\" \"x\""),
+                exec("Chat.Command /RevokeRole \"Moderator\" \"x\""),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_minigames() {
+        let player = json!({
+            "name": "x",
+            "id": "3f5108a0-c929-4e77-a115-21f65096887b",
+            "controller": "BP_PlayerController_C_2147482146",
+            "state": "BP_PlayerState_C_2147482120"
+        });
+        let mock = MockOmegga::new();
+        mock.respond_to(
+            "getMinigames",
+            Some(json!([
+                {
+                    "index": -1,
+                    "name": "GLOBAL",
+                    "ruleset": "GLOBAL",
+                    "members": [],
+                    "teams": []
+                },
+                {
+                    "index": 0,
+                    "name": "Deathmatch",
+                    "ruleset": "BP_Ruleset_C_2147482025",
+                    "members": [player],
+                    "teams": [
+                        {
+                            "name": "Red Team",
+                            "team": "BP_Team_C_2147482022",
+                            "color": [255, 0, 0, 255],
+                            "members": [player]
+                        }
+                    ],
+                    "ownerId": "3f5108a0-c929-4e77-a115-21f65096887b"
+                }
+            ])),
+        );
+        let _events = mock.spawn();
+
+        let minigames = mock.omegga().get_minigames().await.unwrap();
+        assert_eq!(minigames.len(), 2);
+        assert_eq!(
+            (minigames[0].index, minigames[0].name.as_str()),
+            (-1, "GLOBAL")
+        );
+
+        let deathmatch = &minigames[1];
+        assert_eq!(deathmatch.index, 0);
+        assert_eq!(deathmatch.ruleset, "BP_Ruleset_C_2147482025");
+        assert_eq!(deathmatch.members[0].name, "x");
+        assert_eq!(deathmatch.teams[0].name, "Red Team");
+        assert_eq!(deathmatch.teams[0].team, "BP_Team_C_2147482022");
+        assert_eq!(deathmatch.teams[0].members[0].id, deathmatch.members[0].id);
+        assert_eq!(deathmatch.teams[0].extra["color"], json!([255, 0, 0, 255]));
+        assert_eq!(
+            deathmatch.extra["ownerId"],
+            "3f5108a0-c929-4e77-a115-21f65096887b"
+        );
+    }
+
+    #[tokio::test]
+    async fn controls_minigames() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        omegga.reset_minigame(0);
+        omegga.next_round_minigame(1);
+        omegga.delete_minigame(2);
+
+        wait_for_count(&mock, 3).await;
+        assert_eq!(
+            sent_json(&mock),
+            [
+                exec("Server.Minigames.Reset 0"),
+                exec("Server.Minigames.NextRound 1"),
+                exec("Server.Minigames.Delete 2"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_mismatched_params_without_writing() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+
+        assert!(matches!(
+            omegga.write_notification_checked("whisper", Some(json!(["x", "hi"]))),
+            Err(WriteError::InvalidParams { method, .. }) if method == "whisper"
+        ));
+        assert!(omegga
+            .write_checked(rpc::Message::request(
+                rpc::RequestId::Int(0),
+                "store.set".into(),
+                Some(json!(["key"])),
+            ))
+            .is_err());
+        assert!(omegga
+            .request_checked("getPlayers", Some(json!([1])))
+            .is_err());
+        tokio::task::yield_now().await;
+        assert!(mock.sent().is_empty());
+
+        omegga
+            .write_notification_checked("broadcast", Some(json!("hi")))
+            .unwrap();
+        // methods not in the table aren't checked
+        omegga
+            .write_notification_checked("myPlugin:thing", Some(json!([1])))
+            .unwrap();
+        wait_for_count(&mock, 2).await;
+        assert_eq!(
+            sent_json(&mock),
+            [
+                json!({"jsonrpc": "2.0", "method": "broadcast", "params": "hi"}),
+                json!({"jsonrpc": "2.0", "method": "myPlugin:thing", "params": [1]}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_positions_are_errors() {
+        let mock = MockOmegga::new();
+        mock.respond_to("getAllPlayerPositions", Some(json!([{"pos": [0, 0, 0]}])));
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        assert!(matches!(
+            omegga.get_all_player_positions().await,
+            Err(ResponseError::Deserialize(_))
+        ));
+        assert!(matches!(
+            omegga.get_all_positions().await,
+            Err(ResponseError::Deserialize(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn finds_players_in_radius() {
+        let player = |name: &str| json!({"name": name, "id": format!("{}-id", name)});
+        let mock = MockOmegga::new();
+        mock.respond_to(
+            "getAllPlayerPositions",
+            Some(json!([
+                // exactly on the edge, which counts as inside
+                {"player": player("near"), "pos": [30.0, 40.0, 0.0]},
+                // within the radius horizontally, but far above the center
+                {"player": player("above"), "pos": [0.0, 0.0, 100.0]},
+                {"player": player("far"), "pos": [60.0, 0.0, 0.0]},
+                {"player": player("dead"), "pos": null}
+            ])),
+        );
+        let _events = mock.spawn();
+
+        let players = mock
+            .omegga()
+            .players_in_radius((0.0, 0.0, 0.0), 50.0)
+            .await
+            .unwrap();
+        let names: Vec<_> = players.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["near"]);
+    }
+
+    #[tokio::test]
+    async fn players_in_radius_fails_on_malformed_positions() {
+        let mock = MockOmegga::new();
+        mock.respond_to("getAllPlayerPositions", Some(json!({"players": []})));
+        let _events = mock.spawn();
+
+        assert!(matches!(
+            mock.omegga().players_in_radius((0.0, 0.0, 0.0), 50.0).await,
+            Err(ResponseError::Deserialize(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn kicks_and_bans_players() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        omegga.kick_player("x", None).unwrap();
+        omegga.kick_player("x", Some("spam".into())).unwrap();
+        omegga.ban_player("x", None, None).unwrap();
+        omegga
+            .ban_player("x", Some("griefing".into()), Some(Duration::from_secs(90)))
+            .unwrap();
+
+        wait_for_count(&mock, 4).await;
+        assert_eq!(
+            sent_json(&mock),
+            vec![
+                exec(r#"Chat.Command /Kick "x" """#),
+                exec(r#"Chat.Command /Kick "x" "spam""#),
+                exec(r#"Chat.Command /Ban "x" -1 """#),
+                exec(r#"Chat.Command /Ban "x" 2 "griefing""#),
+            ]
+        );
+
+        assert!(omegga.kick_player("", None).is_err());
+        assert!(omegga.ban_player("x", Some("\"".into()), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn loads_bricks_with_options() {
+        let mock = MockOmegga::new();
+        mock.respond_to("loadBricks", None);
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        omegga
+            .load_bricks_with("default", LoadOptions::default())
+            .await
+            .unwrap();
+        let options = LoadOptions {
+            offset: (10, -20, 30),
+            quiet: false,
+            correct_palette: true,
+            correct_custom: true,
+        };
+        omegga.load_bricks_with("custom", options).await.unwrap();
+
+        assert_eq!(
+            sent_json(&mock),
+            vec![
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": 0,
+                    "method": "loadBricks",
+                    "params": {
+                        "name": "default",
+                        "offX": 0,
+                        "offY": 0,
+                        "offZ": 0,
+                        "quiet": true,
+                        "correctPalette": false,
+                        "correctCustom": false
+                    }
+                }),
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "loadBricks",
+                    "params": {
+                        "name": "custom",
+                        "offX": 10,
+                        "offY": -20,
+                        "offZ": 30,
+                        "quiet": false,
+                        "correctPalette": true,
+                        "correctCustom": true
+                    }
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_role_permissions() {
+        let mock = MockOmegga::new();
+        mock.respond_to(
+            "getRoleSetup",
+            Some(json!({
+                "roles": [
+                    {
+                        "name": "Moderator",
+                        "permissions": [
+                            {"name": "Bricks.ClearAll", "state": "Allowed"},
+                            {"name": "Moderation.Ban", "state": "Forbidden"},
+                            {"name": "Map.Change", "state": "Unchanged"}
+                        ],
+                        "color": {"r": 0, "g": 128, "b": 255, "a": 255},
+                        "bHasColor": true
+                    }
+                ],
+                "defaultRole": {
+                    "name": "Default",
+                    "permissions": [{"name": "Bricks.Save", "state": "Allowed"}],
+                    "color": {"r": 255, "g": 255, "b": 255, "a": 255}
+                },
+                "ownerRoleColor": {"r": 255, "g": 0, "b": 0, "a": 255}
+            })),
+        );
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        assert_eq!(
+            omegga.get_role_permissions("Moderator").await.unwrap(),
+            Some(HashMap::from([
+                ("Bricks.ClearAll".to_string(), true),
+                ("Moderation.Ban".to_string(), false),
+            ]))
+        );
+        assert_eq!(
+            omegga.get_role_permissions("Default").await.unwrap(),
+            Some(HashMap::from([("Bricks.Save".to_string(), true)]))
+        );
+        assert_eq!(omegga.get_role_permissions("Nobody").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn environment_round_trips() {
+        let preset = json!({
+            "formatVersion": "1",
+            "presetVersion": "1",
+            "type": "Environment",
+            "data": {
+                "groups": {
+                    "Sky": {
+                        "timeOfDay": 12.0,
+                        "timeChangeSpeed": 1.0,
+                        "sunAngle": 40.0,
+                        "weatherIntensity": 0.0,
+                        "rainSnow": 0.0,
+                        "cloudCoverage": 0.3,
+                        "fogDensity": 0.2,
+                        "skyIntensity": 1.5,
+                        "sunScale": 1.0
+                    },
+                    "GroundPlate": {"variance": 0.0, "groundColor": {"r": 0, "g": 5, "b": 10, "a": 255}}
+                }
+            }
+        });
+        let mock = MockOmegga::new();
+        mock.respond_to("getEnvironmentData", Some(preset.clone()));
+        mock.respond_to("loadEnvironmentData", None);
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        let mut environment = omegga.get_environment_data().await.unwrap();
+        environment.data.groups.sky.as_mut().unwrap().time_of_day = Some(18.5);
+        omegga.set_environment_data(&environment).await.unwrap();
+
+        let mut expected = preset;
+        expected["data"]["groups"]["Sky"]["timeOfDay"] = json!(18.5);
+        assert_eq!(
+            sent_json(&mock)[1],
+            json!({"jsonrpc": "2.0", "id": 1, "method": "loadEnvironmentData", "params": expected})
+        );
+    }
+
+    #[tokio::test]
+    async fn saves_bricks_for_each_target() {
+        let mock = MockOmegga::new();
+        mock.respond_to("saveBricks", None);
+        mock.respond_to("getSaveData", Some(json!({"bricks": []})));
+        mock.respond_to("writeSaveData", None);
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        omegga
+            .save_bricks_with("all", SaveTarget::All)
+            .await
+            .unwrap();
+        let region = BoundingBox {
+            center: (0.0, 0.0, 10.0),
+            extent: (20.0, 20.0, 10.0),
+        };
+        omegga
+            .save_bricks_with("region", SaveTarget::Region(region))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sent_json(&mock),
+            vec![
+                json!({"jsonrpc": "2.0", "id": 0, "method": "saveBricks", "params": "all"}),
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "getSaveData",
+                    "params": {"center": [0.0, 0.0, 10.0], "extent": [20.0, 20.0, 10.0]}
+                }),
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": 2,
+                    "method": "writeSaveData",
+                    "params": {"name": "region", "data": {"bricks": []}}
+                }),
+            ]
+        );
+
+        assert!(matches!(
+            omegga.save_bricks_with("", SaveTarget::All).await,
+            Err(ResponseError::Write(WriteError::InvalidArgument(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn request_ids_count_up_from_zero() {
+        let mock = MockOmegga::new();
+        let omegga = mock.omegga();
+        let _pending = (0..3)
+            .map(|_| omegga.request("getPlayers", None))
+            .collect::<Vec<_>>();
+
+        mock.wait_for_sent(|message| {
+            matches!(message, rpc::Message::Request { id, .. } if *id == rpc::RequestId::Int(2))
+        })
+        .await;
+        let ids = mock
+            .sent()
+            .into_iter()
+            .map(|message| match message {
+                rpc::Message::Request { id, .. } => id,
+                message => panic!("unexpected message {}", message),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1, 2].map(rpc::RequestId::Int));
+    }
+
+    #[tokio::test]
+    async fn clones_share_requests() {
+        let mock = MockOmegga::new();
+        mock.respond_to("getPlayers", Some(json!([])));
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        let clone = omegga.clone();
+        let task = tokio::spawn(async move { clone.request("getPlayers", None).await });
+        assert_eq!(task.await.unwrap().unwrap(), Some(json!([])));
+        // the clone took id 0 from the shared counter
+        assert_eq!(omegga.next_request_id(), rpc::RequestId::Int(1));
+    }
+
+    #[tokio::test]
+    async fn routes_responses_with_string_ids() {
+        let mock = MockOmegga::new();
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        let id = "0b7f4a9e-6a9d-4b55-9d6e-4f3b8c1e2a10";
+        let other = omegga.request("getPlayers", None);
+        let pending = omegga.request_with_id(id, "getPlayers", None);
+        mock.send_line(format!(
+            r#"{{"jsonrpc":"2.0","id":"{}","result":["string"]}}"#,
+            id
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(pending.await.unwrap(), Some(json!(["string"])));
+        assert!(omegga.awaiter_txs.contains_key(&rpc::RequestId::Int(0)));
+        drop(other);
+    }
+
+    #[test]
+    fn request_ids_wrap_on_overflow() {
+        let omegga = Omegga::builder()
+            .writer(io::sink())
+            .starting_id(i32::MAX)
+            .build();
+        assert_eq!(omegga.next_request_id(), rpc::RequestId::Int(i32::MAX));
+        assert_eq!(omegga.next_request_id(), rpc::RequestId::Int(i32::MIN));
+    }
+
+    /// A writer that always fails, like a closed pipe.
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A writer collecting everything written to it.
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Collect {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failing_writes_are_errors() {
+        let output = Collect::default();
+        let omegga = Omegga::builder().writer(output.clone()).build();
+
+        // a map with non-string keys can't be serialized as JSON
+        let unserializable = HashMap::from([((1, 2), 3)]);
+        assert!(matches!(
+            omegga.write_line(&unserializable),
+            Err(WriteError::Serialize(_))
+        ));
+        assert!(output.0.lock().unwrap().is_empty());
+
+        let broken = Omegga::builder().writer(BrokenPipe).build();
+        let message = rpc::Message::notification("info".into(), None);
+        assert!(matches!(
+            broken.try_write(message.clone()),
+            Err(WriteError::Io(_))
+        ));
+        // and `write` reports it rather than panicking
+        broken.write(message);
+    }
+
+    #[test]
+    fn steps_ids() {
+        let omegga = Omegga::builder()
+            .writer(io::sink())
+            .starting_id(1)
+            .id_step(2)
+            .build();
+        let ids = (0..3).map(|_| omegga.next_request_id()).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 3, 5].map(rpc::RequestId::Int));
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be 0")]
+    fn rejects_a_zero_id_step() {
+        Omegga::builder().id_step(0);
+    }
+
+    #[test]
+    fn dropping_an_awaiter_removes_its_entry() {
+        let omegga = omegga();
+        drop(omegga.request_with_id("dropped", "plugin.get", None));
+        assert!(omegga.awaiter_txs.is_empty());
+    }
+}
@@ -0,0 +1,577 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use dashmap::mapref::entry::Entry;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt},
+    sync::{
+        broadcast,
+        mpsc::{Sender, UnboundedSender},
+        watch,
+    },
+};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::Counters;
+use crate::{
+    events::{Event, InitData},
+    rpc, AwaiterMap, BatchErrorQueue, Middleware, ParseError,
+};
+
+/// Where the listener sends events.
+pub(crate) enum EventSender {
+    Unbounded(UnboundedSender<Event>),
+    /// Sending waits for room in the channel, which stops the listener from
+    /// reading any further messages in the meantime.
+    Bounded(Sender<Event>),
+    /// Events are only sent to subscribers, as for [`Omegga::spawn_raw`](crate::Omegga::spawn_raw).
+    Discard,
+}
+
+impl EventSender {
+    async fn send(&self, event: Event) {
+        match self {
+            EventSender::Unbounded(tx) => {
+                let _ = tx.send(event);
+            }
+            EventSender::Bounded(tx) => {
+                let _ = tx.send(event).await;
+            }
+            EventSender::Discard => (),
+        }
+    }
+}
+
+/// Reads messages from Omegga, routing responses to their awaiters and
+/// everything else to the event channel.
+pub(crate) struct Listener {
+    pub awaiter_txs: Arc<AwaiterMap>,
+    pub batch_error_txs: Arc<BatchErrorQueue>,
+    pub events: EventSender,
+    pub errors: Option<UnboundedSender<ParseError>>,
+    pub raw: Option<UnboundedSender<(String, rpc::Message)>>,
+    pub shutdown: watch::Receiver<()>,
+    pub listening: Arc<AtomicBool>,
+    pub incoming: Arc<[Middleware]>,
+    pub subscribers: broadcast::Sender<Event>,
+    pub forward_orphans: bool,
+    pub init: Arc<watch::Sender<Option<InitData>>>,
+    #[cfg(feature = "metrics")]
+    pub counters: Arc<crate::metrics::Counters>,
+}
+
+/// Cleans up after a listener that panicked, which the normal exits in
+/// [`Listener::run`] don't get to do.
+struct PanicGuard {
+    listening: Arc<AtomicBool>,
+    awaiter_txs: Arc<AwaiterMap>,
+    batch_error_txs: Arc<BatchErrorQueue>,
+}
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            return;
+        }
+
+        self.listening.store(false, Ordering::SeqCst);
+        self.awaiter_txs.clear();
+        // a second panic here would abort, so skip a poisoned queue
+        if let Ok(mut queue) = self.batch_error_txs.lock() {
+            queue.clear();
+        }
+    }
+}
+
+impl Listener {
+    pub async fn run<R: AsyncBufRead + Unpin>(mut self, reader: R) {
+        let _guard = PanicGuard {
+            listening: Arc::clone(&self.listening),
+            awaiter_txs: Arc::clone(&self.awaiter_txs),
+            batch_error_txs: Arc::clone(&self.batch_error_txs),
+        };
+
+        let mut lines = reader.lines();
+        let disconnected = loop {
+            let line = tokio::select! {
+                line = lines.next_line() => line,
+                // an error means the last `Omegga` was dropped, which isn't a shutdown
+                Ok(()) = self.shutdown.changed() => break false,
+            };
+
+            let line = match line {
+                Ok(Some(line)) => line,
+
+                // Omegga closed the pipe
+                Ok(None) => break true,
+
+                // the reader is unusable, so stop listening
+                Err(error) => {
+                    eprintln!("failed to read from Omegga: {}", error);
+                    break true;
+                }
+            };
+
+            self.handle_line(line).await;
+        };
+
+        // `Omegga::shutdown` already cleared the flag, and a listener spawned since then
+        // may have set it again, so only a disconnect clears it here
+        if disconnected && !matches!(self.shutdown.has_changed(), Ok(true)) {
+            // clear the flag first, so the plugin can spawn again as soon as it sees the event
+            self.listening.store(false, Ordering::SeqCst);
+        }
+        if disconnected {
+            // nothing can answer pending requests any more, so fail them like
+            // `Omegga::shutdown` does, by dropping their transmitters
+            self.awaiter_txs.clear();
+            self.batch_error_txs.lock().unwrap().clear();
+            self.emit(Event::Disconnected).await;
+        }
+    }
+
+    /// Handle a line read from Omegga. A partial line is already held back by the reader
+    /// until its newline arrives, and a line holding several messages back to back is
+    /// split into each of them. A malformed message ends the line, as there's no telling
+    /// where the next one starts.
+    async fn handle_line(&self, line: String) {
+        let values = serde_json::Deserializer::from_str(&line).into_iter::<Value>();
+        for value in values {
+            match value {
+                Ok(value) => self.handle_value(&line, value).await,
+                Err(error) => return self.report(line.clone(), error),
+            }
+        }
+    }
+
+    async fn handle_value(&self, line: &str, value: Value) {
+        match value {
+            // a batch, usually of responses, routed one message at a time
+            Value::Array(messages) => {
+                for message in messages {
+                    match serde_json::from_value::<rpc::Message>(message) {
+                        Ok(message) => self.handle_message(line, message).await,
+                        Err(error) => self.report(line.to_string(), error),
+                    }
+                }
+            }
+
+            // an error for a whole batch, which can't be matched to a single request
+            Value::Object(ref object)
+                if object.get("id").is_some_and(Value::is_null) && object.contains_key("error") =>
+            {
+                match serde_json::from_value::<rpc::Error>(object["error"].clone()) {
+                    Ok(error) => self.fail_batch(error),
+                    Err(error) => self.report(line.to_string(), error),
+                }
+            }
+
+            value => match serde_json::from_value::<rpc::Message>(value) {
+                Ok(message) => self.handle_message(line, message).await,
+                Err(error) => self.report(line.to_string(), error),
+            },
+        }
+    }
+
+    /// Run the inbound middleware over a message, in registration order.
+    fn tap(&self, mut message: rpc::Message) -> rpc::Message {
+        for middleware in self.incoming.iter() {
+            middleware(&mut message);
+        }
+        message
+    }
+
+    async fn handle_message(&self, line: &str, message: rpc::Message) {
+        if let Some(raw) = &self.raw {
+            let _ = raw.send((line.to_string(), message.clone()));
+        }
+
+        let event = match self.tap(message) {
+            // Handle responses
+            rpc::Message::Response {
+                id, result, error, ..
+            } => {
+                return self
+                    .route_response(rpc::Response { id, result, error })
+                    .await
+            }
+
+            // Handle requests
+            rpc::Message::Request {
+                id, method, params, ..
+            } => {
+                #[cfg(feature = "metrics")]
+                Counters::increment(&self.counters.requests_received);
+                Event::from_request(id, &method, params)
+            }
+
+            // Handle notifications
+            rpc::Message::Notification { method, params, .. } => {
+                #[cfg(feature = "metrics")]
+                Counters::increment(&self.counters.notifications_received);
+                match Event::from_notification(&method, params) {
+                    Ok(event) => event,
+                    Err(error) => return self.report(line.to_string(), error),
+                }
+            }
+        };
+
+        if let Some(event) = event {
+            #[cfg(feature = "log")]
+            log::trace!("event {}", event.name());
+            if let Event::Init { id, config } = &event {
+                self.init.send_replace(Some(InitData {
+                    id: id.clone(),
+                    config: config.clone(),
+                }));
+            }
+            self.emit(event).await;
+        }
+    }
+
+    /// Send an event to the subscribers and then the event channel.
+    async fn emit(&self, event: Event) {
+        // skip the clone when nobody is subscribed
+        if self.subscribers.receiver_count() > 0 {
+            let _ = self.subscribers.send(event.clone());
+        }
+        self.events.send(event).await;
+    }
+
+    async fn route_response(&self, response: rpc::Response) {
+        match self.awaiter_txs.entry(response.id.clone()) {
+            Entry::Occupied(entry) => {
+                #[cfg(feature = "metrics")]
+                Counters::increment(&self.counters.responses_matched);
+                let (_, sender) = entry.remove_entry();
+                let _ = sender.send(response);
+            }
+            Entry::Vacant(entry) => {
+                // release the shard lock before waiting on the event channel
+                drop(entry);
+                #[cfg(feature = "metrics")]
+                Counters::increment(&self.counters.orphan_responses);
+                #[cfg(feature = "log")]
+                log::debug!("response to #{} with no request waiting", response.id);
+                if self.forward_orphans {
+                    self.emit(Event::OrphanResponse(response)).await;
+                }
+            }
+        }
+    }
+
+    fn fail_batch(&self, error: rpc::Error) {
+        // batches are answered in order, so the error belongs to the oldest batch
+        // that is still waiting, skipping any that were answered or given up on
+        let mut queue = self.batch_error_txs.lock().unwrap();
+        while let Some(batch) = queue.pop_front() {
+            let waiting = batch.ids.iter().any(|id| self.awaiter_txs.contains_key(id));
+            if waiting && batch.tx.send(error.clone()).is_ok() {
+                return;
+            }
+        }
+
+        // with no batch waiting, it's the error for a single request that Omegga
+        // couldn't read the ID of, which can't be matched to its request
+        #[cfg(feature = "metrics")]
+        Counters::increment(&self.counters.orphan_responses);
+        #[cfg(feature = "log")]
+        log::warn!("error with no request ID from Omegga: {}", error);
+    }
+
+    fn report(&self, line: String, error: serde_json::Error) {
+        #[cfg(feature = "log")]
+        log::warn!("failed to parse message from Omegga: {}", error);
+        #[cfg(feature = "metrics")]
+        Counters::increment(&self.counters.parse_errors);
+
+        if let Some(errors) = &self.errors {
+            let _ = errors.send(ParseError { line, error });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::io::{AsyncWriteExt, BufReader};
+
+    use crate::{events::Event, mock::MockOmegga, rpc, Omegga, ResponseError};
+
+    #[tokio::test]
+    async fn disconnecting_fails_pending_requests() {
+        let mock = MockOmegga::new();
+        let _events = mock.spawn();
+
+        let pending = mock.omegga().request("getPlayers", None);
+        mock.wait_for_sent(|_| true).await;
+        mock.close().await;
+        assert!(matches!(pending.await, Err(ResponseError::Recv(_))));
+    }
+
+    #[tokio::test]
+    async fn shutting_down_fails_pending_requests_and_allows_a_respawn() {
+        let mock = MockOmegga::new();
+        let mut events = mock.spawn();
+        let omegga = mock.omegga();
+
+        let pending = omegga.request("getPlayers", None);
+        mock.wait_for_sent(|_| true).await;
+        omegga.shutdown();
+
+        // respawn straight away, before the old listener has exited
+        let (mut input, output) = tokio::io::duplex(1024);
+        let mut respawned = omegga.spawn_from(BufReader::new(output));
+        assert!(omegga.is_listening());
+
+        assert!(matches!(pending.await, Err(ResponseError::Recv(_))));
+        assert!(events.recv().await.is_none());
+
+        input
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"chat\",\"params\":[\"x\",\"hi\"]}\n")
+            .await
+            .unwrap();
+        assert!(matches!(
+            respawned.recv().await,
+            Some(Event::Chat { message, .. }) if message == "hi"
+        ));
+        assert!(omegga.is_listening());
+    }
+
+    #[tokio::test]
+    async fn a_second_listener_is_refused() {
+        let mock = MockOmegga::new();
+        let mut events = mock.spawn();
+
+        let (mut input, output) = tokio::io::duplex(1024);
+        let mut second = mock.omegga().spawn_from(BufReader::new(output));
+        // the refused listener dropped its reader without reading anything
+        assert!(input.write_all(b"{}\n").await.is_err());
+        assert!(second.recv().await.is_none());
+
+        mock.notify("chat", Some(json!(["x", "hi"]))).await.unwrap();
+        assert!(matches!(
+            events.recv().await,
+            Some(Event::Chat { message, .. }) if message == "hi"
+        ));
+        assert!(mock.omegga().is_listening());
+    }
+
+    #[tokio::test]
+    async fn malformed_notifications_are_reported() {
+        let mock = MockOmegga::new();
+        let (mut events, mut errors) = mock.spawn_with_errors();
+
+        mock.notify("chat", Some(json!([1]))).await.unwrap();
+        mock.notify("line", None).await.unwrap();
+        assert!(errors.recv().await.unwrap().line.contains("chat"));
+        assert!(errors.recv().await.unwrap().line.contains("line"));
+
+        // the listener keeps going
+        mock.notify("chat", Some(json!(["x", "hi"]))).await.unwrap();
+        assert!(matches!(events.recv().await, Some(Event::Chat { .. })));
+    }
+
+    #[tokio::test]
+    async fn routes_each_message_in_a_batched_line() {
+        let mock = MockOmegga::new();
+        let mut events = mock.spawn();
+        let omegga = mock.omegga();
+
+        let first = omegga.request("getPlayers", None);
+        let second = omegga.request("getRoleSetup", None);
+        mock.send_line(concat!(
+            r#"[{"jsonrpc":"2.0","id":1,"result":{"roles":[]}},"#,
+            r#"{"jsonrpc":"2.0","id":0,"result":[]}]"#,
+        ))
+        .await
+        .unwrap();
+        assert_eq!(first.await.unwrap(), Some(json!([])));
+        assert_eq!(second.await.unwrap(), Some(json!({"roles": []})));
+
+        mock.send_line(concat!(
+            r#"[{"jsonrpc":"2.0","method":"chat","params":["x","hi"]},"#,
+            r#"{"jsonrpc":"2.0","method":"chat","params":["y","there"]}]"#,
+        ))
+        .await
+        .unwrap();
+        assert!(matches!(
+            events.recv().await,
+            Some(Event::Chat { message, .. }) if message == "hi"
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(Event::Chat { message, .. }) if message == "there"
+        ));
+    }
+
+    #[tokio::test]
+    async fn incoming_requests_keep_their_ids() {
+        let mock = MockOmegga::new();
+        let mut events = mock.spawn();
+
+        let requests = [
+            (
+                rpc::RequestId::Int(7),
+                "init",
+                Some(json!({"greeting": "hi"})),
+            ),
+            (rpc::RequestId::from("stop-1"), "stop", None),
+            (
+                rpc::RequestId::Int(8),
+                "plugin:emit",
+                Some(json!(["ping", "other-plugin", 1])),
+            ),
+            (rpc::RequestId::Int(9), "plugin:emit", Some(json!([1]))),
+            (rpc::RequestId::from("custom"), "myPlugin:call", None),
+        ];
+        for (id, method, params) in requests.clone() {
+            mock.request(id, method, params).await.unwrap();
+        }
+
+        for (id, method, _) in requests {
+            let event = events.recv().await.unwrap();
+            assert_eq!(event.request_id(), Some(&id), "{} lost its id", method);
+        }
+    }
+
+    /// A tiny xorshift generator, so the chunked input is random but repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    #[tokio::test]
+    async fn parses_arbitrarily_chunked_input_exactly_once() {
+        const MESSAGES: usize = 30;
+
+        for seed in 1..=50 {
+            let mut rng = Rng(seed);
+
+            // messages separated by newlines, or back to back on one line
+            let mut input = Vec::new();
+            for i in 0..MESSAGES {
+                let message = format!("{}{}", "é".repeat(rng.below(4)), i);
+                input.extend(
+                    serde_json::to_vec(&json!({
+                        "jsonrpc": "2.0",
+                        "method": "chat",
+                        "params": ["x", message]
+                    }))
+                    .unwrap(),
+                );
+                input.extend_from_slice([&b"\n"[..], b"\r\n", b"", b" "][rng.below(4)]);
+            }
+            input.push(b'\n');
+
+            let omegga = Omegga::builder().writer(std::io::sink()).build();
+            let (mut writer, reader) = tokio::io::duplex(16);
+            let mut events = omegga.spawn_from(BufReader::new(reader));
+            let feed = tokio::spawn(async move {
+                let mut rest = &input[..];
+                while !rest.is_empty() {
+                    let (chunk, tail) = rest.split_at(1 + rng.below(rest.len().min(40)));
+                    writer.write_all(chunk).await.unwrap();
+                    tokio::task::yield_now().await;
+                    rest = tail;
+                }
+            });
+
+            let mut seen = Vec::new();
+            while let Some(event) = events.recv().await {
+                match event {
+                    Event::Chat { message, .. } => {
+                        seen.push(message.trim_start_matches('é').parse::<usize>().unwrap())
+                    }
+                    Event::Disconnected => break,
+                    event => panic!("unexpected event {:?}", event),
+                }
+            }
+            feed.await.unwrap();
+            assert_eq!(seen, (0..MESSAGES).collect::<Vec<_>>(), "seed {}", seed);
+        }
+    }
+
+    #[tokio::test]
+    async fn panicking_listener_fails_pending_requests() {
+        let mock = MockOmegga::from_builder(Omegga::builder().incoming(|message| {
+            if let rpc::Message::Notification { method, .. } = message {
+                assert_ne!(method, "boom", "listener panic for the test");
+            }
+        }));
+        let mut events = mock.spawn();
+
+        let pending = mock.omegga().request("getPlayers", None);
+        mock.wait_for_sent(|_| true).await;
+        mock.notify("boom", None).await.unwrap();
+
+        assert!(matches!(pending.await, Err(ResponseError::Recv(_))));
+        assert!(events.recv().await.is_none());
+        assert!(!mock.omegga().is_listening());
+    }
+
+    #[tokio::test]
+    async fn batch_errors_go_to_the_waiting_batch() {
+        let mock = MockOmegga::new();
+        let _events = mock.spawn();
+
+        let batch = mock.omegga().request_batch(vec![
+            ("getPlayers".into(), None),
+            ("getRoleSetup".into(), None),
+        ]);
+        mock.wait_for_sent(|_| true).await;
+        mock.send_line(
+            r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32600,"message":"invalid"}}"#,
+        )
+        .await
+        .unwrap();
+
+        let results = batch.await;
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|r| matches!(r, Err(ResponseError::Rpc(error)) if error.code() == -32600)));
+    }
+
+    #[tokio::test]
+    async fn batch_errors_skip_finished_batches_and_single_requests() {
+        let mock = MockOmegga::new();
+        mock.respond_to("getPlayers", Some(json!([])));
+        let _events = mock.spawn();
+        let omegga = mock.omegga();
+
+        // a batch already answered but not yet awaited isn't waiting any more
+        let answered = omegga.request_batch(vec![("getPlayers".into(), None)]);
+        while !omegga.awaiter_txs.is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        let pending = omegga.request_with_id("single", "getRoleSetup", None);
+        mock.send_line(
+            r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"parse error"}}"#,
+        )
+        .await
+        .unwrap();
+        mock.send(rpc::Message::response(
+            "single".into(),
+            Some(json!({})),
+            None,
+        ))
+        .await
+        .unwrap();
+        assert!(matches!(pending.await, Ok(Some(_))));
+        assert!(matches!(&answered.await[..], [Ok(Some(_))]));
+
+        // a finished batch leaves nothing behind to misroute an error to
+        assert!(omegga.batch_error_txs.lock().unwrap().is_empty());
+    }
+}
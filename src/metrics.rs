@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of the message counters, from [`Omegga::stats`](crate::Omegga::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Notifications received from Omegga.
+    pub notifications_received: u64,
+    /// Requests received from Omegga.
+    pub requests_received: u64,
+    /// Responses delivered to a waiting request.
+    pub responses_matched: u64,
+    /// Responses with no request waiting for them, such as ones for a timed out or
+    /// cancelled request, or with an ID that was never sent.
+    pub orphan_responses: u64,
+    /// Lines that failed to parse as RPC messages.
+    pub parse_errors: u64,
+    /// Bytes written out to Omegga.
+    pub bytes_written: u64,
+}
+
+/// The live counters behind [`Stats`], shared between an `Omegga` and its listeners.
+#[derive(Default)]
+pub(crate) struct Counters {
+    pub notifications_received: AtomicU64,
+    pub requests_received: AtomicU64,
+    pub responses_matched: AtomicU64,
+    pub orphan_responses: AtomicU64,
+    pub parse_errors: AtomicU64,
+    pub bytes_written: AtomicU64,
+}
+
+impl Counters {
+    /// Add one to a counter. The counters are independent, so no ordering is needed.
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: usize) {
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            notifications_received: self.notifications_received.load(Ordering::Relaxed),
+            requests_received: self.requests_received.load(Ordering::Relaxed),
+            responses_matched: self.responses_matched.load(Ordering::Relaxed),
+            orphan_responses: self.orphan_responses.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
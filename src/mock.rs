@@ -0,0 +1,349 @@
+//! An in-memory stand-in for Omegga, for testing plugins without a server.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{
+    io::{duplex, AsyncWriteExt, BufReader, DuplexStream},
+    sync::{mpsc, Notify},
+};
+
+use crate::{rpc, EventReceiver, Omegga, OmeggaBuilder, ParseErrorReceiver};
+
+/// How many bytes of messages to the plugin can be waiting to be read.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Plays the part of Omegga over in-memory pipes: it answers requests with canned
+/// responses, feeds messages to the plugin, and keeps every message the plugin sends.
+///
+/// Create one from within a Tokio runtime, since it spawns a task answering requests.
+pub struct MockOmegga {
+    omegga: Omegga,
+    reader: Mutex<Option<BufReader<DuplexStream>>>,
+    input: Arc<tokio::sync::Mutex<Option<DuplexStream>>>,
+    responses: Arc<Mutex<HashMap<String, rpc::ResponseBody>>>,
+    sent: Arc<Mutex<Vec<rpc::Message>>>,
+    sent_notify: Arc<Notify>,
+}
+
+/// The plugin's output, split into lines for the mock to read.
+struct LineWriter {
+    buffer: Vec<u8>,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl Write for LineWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line = self.buffer.drain(..=end).collect::<Vec<_>>();
+            let _ = self.tx.send(line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MockOmegga {
+    /// Create a mock with a default [`Omegga`] connected to it.
+    pub fn new() -> Self {
+        Self::from_builder(Omegga::builder())
+    }
+
+    /// Create a mock with an [`Omegga`] built from `builder`, replacing its writer.
+    pub fn from_builder(builder: OmeggaBuilder) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let omegga = builder
+            .writer(LineWriter {
+                buffer: Vec::new(),
+                tx,
+            })
+            .build();
+        let (input, output) = duplex(PIPE_CAPACITY);
+
+        let mock = MockOmegga {
+            omegga,
+            reader: Mutex::new(Some(BufReader::new(output))),
+            input: Arc::new(tokio::sync::Mutex::new(Some(input))),
+            responses: Arc::new(Mutex::new(HashMap::new())),
+            sent: Arc::new(Mutex::new(Vec::new())),
+            sent_notify: Arc::new(Notify::new()),
+        };
+
+        let input = Arc::clone(&mock.input);
+        let responses = Arc::clone(&mock.responses);
+        let sent = Arc::clone(&mock.sent);
+        let sent_notify = Arc::clone(&mock.sent_notify);
+        tokio::spawn(async move {
+            while let Some(line) = rx.recv().await {
+                let value = match serde_json::from_slice::<Value>(&line) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+
+                // a batch is answered with a batch of the replies to its requests
+                let (messages, batch) = match value {
+                    Value::Array(values) => (values, true),
+                    value => (vec![value], false),
+                };
+                let messages = messages
+                    .into_iter()
+                    .filter_map(|value| serde_json::from_value::<rpc::Message>(value).ok())
+                    .collect::<Vec<_>>();
+
+                let mut replies = messages
+                    .iter()
+                    .filter_map(|message| match message {
+                        rpc::Message::Request { id, method, .. } => {
+                            let body = responses.lock().unwrap().get(method).cloned();
+                            body.map(|body| response(id.clone(), body))
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                sent.lock().unwrap().extend(messages);
+                sent_notify.notify_waiters();
+
+                if batch && !replies.is_empty() {
+                    let _ = write_to(&input, &replies).await;
+                } else if let Some(reply) = replies.pop() {
+                    let _ = write_to(&input, &reply).await;
+                }
+            }
+        });
+
+        mock
+    }
+
+    /// The [`Omegga`] connected to the mock.
+    pub fn omegga(&self) -> Omegga {
+        self.omegga.clone()
+    }
+
+    /// Start the listener reading from the mock, like [`Omegga::spawn`](Omegga::spawn).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listener was already started, with this or [`run`](MockOmegga::run).
+    pub fn spawn(&self) -> EventReceiver {
+        self.omegga.spawn_from(self.take_reader())
+    }
+
+    /// Start the listener like [`spawn`](MockOmegga::spawn), also returning a receiver for
+    /// lines that failed to parse, as with [`Omegga::spawn_with_errors`](Omegga::spawn_with_errors).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listener was already started.
+    pub fn spawn_with_errors(&self) -> (EventReceiver, ParseErrorReceiver) {
+        let (error_tx, error_rx) = mpsc::unbounded_channel();
+        (
+            self.omegga.listen(self.take_reader(), Some(error_tx)),
+            error_rx,
+        )
+    }
+
+    /// Dispatch events to the handlers registered on the connected [`Omegga`] until the
+    /// mock sends `stop` or is [closed](MockOmegga::close), like [`Omegga::run`](Omegga::run).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the listener was already started, with this or [`spawn`](MockOmegga::spawn).
+    pub async fn run(&self) {
+        let reader = self.take_reader();
+        self.omegga.run_from(reader).await
+    }
+
+    fn take_reader(&self) -> BufReader<DuplexStream> {
+        self.reader
+            .lock()
+            .unwrap()
+            .take()
+            .expect("the mock's listener was already started")
+    }
+
+    /// Answer every request for `method` with a successful `result`.
+    pub fn respond_to(&self, method: impl Into<String>, result: Option<Value>) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(method.into(), rpc::ResponseBody::Ok(result));
+    }
+
+    /// Answer every request for `method` with `error`.
+    pub fn respond_err(&self, method: impl Into<String>, error: rpc::Error) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(method.into(), rpc::ResponseBody::Err(error));
+    }
+
+    /// Send a message to the plugin, as if from Omegga.
+    pub async fn send(&self, message: rpc::Message) -> io::Result<()> {
+        write_to(&self.input, &message).await
+    }
+
+    /// Send a line to the plugin as is, for input that isn't a well-formed [`rpc::Message`].
+    /// A newline is added to the end.
+    pub async fn send_line(&self, line: impl Into<String>) -> io::Result<()> {
+        let mut line = line.into().into_bytes();
+        line.push(b'\n');
+        write_bytes(&self.input, &line).await
+    }
+
+    /// Send a notification to the plugin, like `chat` or `join`.
+    pub async fn notify(&self, method: impl Into<String>, params: Option<Value>) -> io::Result<()> {
+        self.send(rpc::Message::notification(method.into(), params))
+            .await
+    }
+
+    /// Send a request to the plugin, like `init` or `stop`. Its response shows up in
+    /// [`sent`](MockOmegga::sent).
+    pub async fn request(
+        &self,
+        id: impl Into<rpc::RequestId>,
+        method: impl Into<String>,
+        params: Option<Value>,
+    ) -> io::Result<()> {
+        self.send(rpc::Message::request(id.into(), method.into(), params))
+            .await
+    }
+
+    /// Close the plugin's input, as when Omegga exits.
+    pub async fn close(&self) {
+        self.input.lock().await.take();
+    }
+
+    /// Every message the plugin has sent so far, in order, with batches split into
+    /// their messages.
+    pub fn sent(&self) -> Vec<rpc::Message> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Take every message the plugin has sent so far, clearing them.
+    pub fn take_sent(&self) -> Vec<rpc::Message> {
+        std::mem::take(&mut *self.sent.lock().unwrap())
+    }
+
+    /// Wait until the plugin has sent a message matching `predicate`, returning it.
+    /// Messages sent before the call count too.
+    ///
+    /// The messages stay locked while `predicate` runs, so it mustn't call
+    /// [`sent`](MockOmegga::sent) or [`take_sent`](MockOmegga::take_sent).
+    pub async fn wait_for_sent<F>(&self, predicate: F) -> rpc::Message
+    where
+        F: Fn(&rpc::Message) -> bool,
+    {
+        loop {
+            // register before checking, so a message sent in between still wakes us
+            let notified = self.sent_notify.notified();
+            if let Some(message) = self.sent.lock().unwrap().iter().find(|m| predicate(m)) {
+                return message.clone();
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Default for MockOmegga {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn response(id: rpc::RequestId, body: rpc::ResponseBody) -> rpc::Message {
+    match body {
+        rpc::ResponseBody::Ok(result) => rpc::Message::response(id, result, None),
+        rpc::ResponseBody::Err(error) => rpc::Message::response(id, None, Some(error)),
+    }
+}
+
+async fn write_to(
+    input: &tokio::sync::Mutex<Option<DuplexStream>>,
+    message: &impl Serialize,
+) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    write_bytes(input, &line).await
+}
+
+async fn write_bytes(
+    input: &tokio::sync::Mutex<Option<DuplexStream>>,
+    bytes: &[u8],
+) -> io::Result<()> {
+    match input.lock().await.as_mut() {
+        Some(input) => input.write_all(bytes).await,
+        None => Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "the mock was closed",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{events::Event, ResponseError};
+
+    #[tokio::test]
+    async fn answers_requests() {
+        let mock = MockOmegga::new();
+        mock.respond_to("plugin.get", Some(json!({"name": "a"})));
+        let _events = mock.spawn();
+
+        let result = mock.omegga().request("plugin.get", None).await.unwrap();
+        assert_eq!(result, Some(json!({"name": "a"})));
+        assert!(matches!(
+            &mock.sent()[..],
+            [rpc::Message::Request { method, .. }] if method == "plugin.get"
+        ));
+    }
+
+    #[tokio::test]
+    async fn answers_batches() {
+        let mock = MockOmegga::new();
+        mock.respond_to("getPlayers", Some(json!([])));
+        mock.respond_err("getRoleSetup", rpc::Error::new(-32000, "no roles", None));
+        let _events = mock.spawn();
+
+        let results = mock
+            .omegga()
+            .request_batch(vec![
+                ("getPlayers".into(), None),
+                ("getRoleSetup".into(), None),
+            ])
+            .await;
+        assert!(matches!(&results[0], Ok(Some(players)) if players == &json!([])));
+        assert!(matches!(&results[1], Err(ResponseError::Rpc(error)) if error.code() == -32000));
+        assert_eq!(mock.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn delivers_notifications_and_disconnects() {
+        let mock = MockOmegga::new();
+        let mut events = mock.spawn();
+
+        mock.notify("chat", Some(json!(["x", "hello"])))
+            .await
+            .unwrap();
+        assert!(matches!(
+            events.recv().await,
+            Some(Event::Chat { player, message }) if player == "x" && message == "hello"
+        ));
+
+        mock.close().await;
+        assert!(matches!(events.recv().await, Some(Event::Disconnected)));
+        assert!(events.recv().await.is_none());
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// What happens to chat messages sent over the
+/// [chat rate](crate::OmeggaBuilder::chat_rate).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Hold the message back until the limit allows it, keeping chat in order.
+    #[default]
+    Delay,
+    /// Drop the message, counting it in [`Omegga::dropped_chat`](crate::Omegga::dropped_chat).
+    Drop,
+}
+
+/// A token bucket limiting outgoing chat, holding up to a second's worth of messages.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    overflow: Overflow,
+    bucket: Mutex<Bucket>,
+    dropped: AtomicU64,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32, overflow: Overflow) -> Self {
+        let rate = f64::from(per_second.max(1));
+        RateLimiter {
+            rate,
+            overflow,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                refilled: Instant::now(),
+            }),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a token for a message, returning how long to wait before sending it,
+    /// or `None` if it should be dropped.
+    pub fn acquire(&self) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Some(Duration::ZERO);
+        }
+
+        match self.overflow {
+            // borrow against future tokens, so each delayed message waits its turn
+            Overflow::Delay => {
+                bucket.tokens -= 1.0;
+                Some(Duration::from_secs_f64(-bucket.tokens / self.rate))
+            }
+            Overflow::Drop => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
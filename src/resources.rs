@@ -47,18 +47,140 @@ impl Colorize for String {
     }
 
     fn size(self, size: i32) -> Self {
-        format!("<size=\"{}\">{}</>", self, size)
+        format!("<size=\"{}\">{}</>", size, self)
     }
 }
 
+/// A chat message built from formatted segments, for [`broadcast`](crate::Omegga::broadcast)
+/// or [`whisper`](crate::Omegga::whisper): `ChatMessage::new().text("hi").color("ff0000").bold()`.
+///
+/// Segment text is escaped, and formatting applies to the last segment added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatMessage {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Segment {
+    text: String,
+    bold: bool,
+    italics: bool,
+    color: Option<String>,
+    size: Option<i32>,
+}
+
+impl ChatMessage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a segment of text, escaped so it displays literally.
+    pub fn text(self, text: impl AsRef<str>) -> Self {
+        self.raw(escape(text.as_ref()))
+    }
+
+    /// Add a segment of markup, used as is.
+    pub fn raw(mut self, markup: impl Into<String>) -> Self {
+        self.segments.push(Segment {
+            text: markup.into(),
+            ..Segment::default()
+        });
+        self
+    }
+
+    pub fn bold(self) -> Self {
+        self.style(|segment| segment.bold = true)
+    }
+
+    pub fn italics(self) -> Self {
+        self.style(|segment| segment.italics = true)
+    }
+
+    /// Color the last segment, with a hex code like `ff0000`.
+    pub fn color(self, code: impl Into<String>) -> Self {
+        let code = code.into();
+        self.style(|segment| segment.color = Some(code))
+    }
+
+    pub fn size(self, size: i32) -> Self {
+        self.style(|segment| segment.size = Some(size))
+    }
+
+    fn style(mut self, f: impl FnOnce(&mut Segment)) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            f(segment);
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for ChatMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for segment in &self.segments {
+            let mut text = segment.text.clone();
+            if segment.bold {
+                text = text.bold();
+            }
+            if segment.italics {
+                text = text.italics();
+            }
+            if let Some(code) = &segment.color {
+                text = text.color(code);
+            }
+            if let Some(size) = segment.size {
+                text = text.size(size);
+            }
+            f.write_str(&text)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ChatMessage> for String {
+    fn from(message: ChatMessage) -> Self {
+        message.to_string()
+    }
+}
+
+/// Escapes text so it is displayed literally in Brickadia chat.
+///
+/// The following characters are escaped:
+/// * `&` becomes `&amp;`
+/// * `;` becomes `&scl;` (a raw `;` would split the underlying console command)
+/// * `<` becomes `&lt;`
+/// * `>` becomes `&gt;`
+///
+/// Escaping `<` and `>` neutralizes every markup tag, including `<color>`, `<b>` and `<size>`.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            ';' => escaped.push_str("&scl;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 /// A player.
+///
+/// Only `name` and `id` are required, since not every Omegga message carries the
+/// controller and state. Any other fields Omegga sends are kept in `extra`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
     pub name: String,
     pub id: String,
+    #[serde(default)]
     pub controller: String,
+    #[serde(default)]
     pub state: String,
+    #[serde(default)]
     pub host: Option<bool>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 /// A player position, which composes a `Player` and their position (a `(f64, f64, f64)`).
@@ -68,6 +190,35 @@ pub struct PlayerPosition {
     pub pos: Option<(f64, f64, f64)>,
 }
 
+/// A position in the world. (De)serializes as an `[x, y, z]` array, like Omegga sends it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(f64, f64, f64)", into = "(f64, f64, f64)")]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Position {
+    /// The straight-line distance to another position, in Unreal units.
+    pub fn distance(&self, other: &Position) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2))
+            .sqrt()
+    }
+}
+
+impl From<(f64, f64, f64)> for Position {
+    fn from((x, y, z): (f64, f64, f64)) -> Self {
+        Self { x, y, z }
+    }
+}
+
+impl From<Position> for (f64, f64, f64) {
+    fn from(pos: Position) -> Self {
+        (pos.x, pos.y, pos.z)
+    }
+}
+
 /// Ghost brick data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GhostBrick {
@@ -88,6 +239,30 @@ pub struct PlayerPaint {
     pub material: String,
     #[serde(rename = "color")]
     pub color: (u8, u8, u8),
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// The cosmetic data Omegga exposes for a player, from
+/// [`Omegga::get_player_appearance`](crate::Omegga::get_player_appearance).
+#[derive(Debug, Clone)]
+pub struct PlayerAppearance {
+    /// The player's name color, as 6-digit hexadecimal.
+    pub name_color: Option<String>,
+    /// What the player is painting with.
+    pub paint: Option<PlayerPaint>,
+}
+
+impl PlayerAppearance {
+    /// The player's name color as RGB, or `None` if it's missing or malformed.
+    pub fn name_color_rgb(&self) -> Option<(u8, u8, u8)> {
+        let hex = self.name_color.as_deref()?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some((channel(0)?, channel(2)?, channel(4)?))
+    }
 }
 
 /// Bounds data.
@@ -100,14 +275,354 @@ pub struct TemplateBounds {
     pub center: (f64, f64, f64),
 }
 
-/// A plugin.
+/// A region of the world, as a center and the distance from it to each face.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub center: (f64, f64, f64),
+    pub extent: (f64, f64, f64),
+}
+
+impl BoundingBox {
+    /// Build a bounding box from its minimum and maximum corners.
+    pub fn from_corners(min: (f64, f64, f64), max: (f64, f64, f64)) -> Self {
+        BoundingBox {
+            center: (
+                (min.0 + max.0) / 2.0,
+                (min.1 + max.1) / 2.0,
+                (min.2 + max.2) / 2.0,
+            ),
+            extent: (
+                (max.0 - min.0) / 2.0,
+                (max.1 - min.1) / 2.0,
+                (max.2 - min.2) / 2.0,
+            ),
+        }
+    }
+
+    /// Compute the bounds of every brick in a save, or `None` for a save with no bricks.
+    ///
+    /// Bricks that aren't procedural don't store their size, so only their position counts.
+    #[cfg(feature = "brs")]
+    pub fn from_save(data: &brickadia::save::SaveData) -> Option<Self> {
+        use brickadia::save::{Direction, Rotation, Size};
+
+        let mut bricks = data.bricks.iter().map(|brick| {
+            let (x, y, z) = match brick.size {
+                Size::Procedural(x, y, z) => (x as i64, y as i64, z as i64),
+                Size::Empty => (0, 0, 0),
+            };
+
+            // orient the size along the brick's direction, then its rotation about it
+            let (mut x, mut y, mut z) = match brick.direction {
+                Direction::XPositive | Direction::XNegative => (z, y, x),
+                Direction::YPositive | Direction::YNegative => (x, z, y),
+                Direction::ZPositive | Direction::ZNegative => (x, y, z),
+            };
+            if matches!(brick.rotation, Rotation::Deg90 | Rotation::Deg270) {
+                match brick.direction {
+                    Direction::XPositive | Direction::XNegative => std::mem::swap(&mut y, &mut z),
+                    Direction::YPositive | Direction::YNegative => std::mem::swap(&mut x, &mut z),
+                    Direction::ZPositive | Direction::ZNegative => std::mem::swap(&mut x, &mut y),
+                }
+            }
+
+            let (px, py, pz) = brick.position;
+            let (px, py, pz) = (px as i64, py as i64, pz as i64);
+            ((px - x, py - y, pz - z), (px + x, py + y, pz + z))
+        });
+
+        let (mut min, mut max) = bricks.next()?;
+        for (lo, hi) in bricks {
+            min = (min.0.min(lo.0), min.1.min(lo.1), min.2.min(lo.2));
+            max = (max.0.max(hi.0), max.1.max(hi.1), max.2.max(hi.2));
+        }
+
+        Some(BoundingBox::from_corners(
+            (min.0 as f64, min.1 as f64, min.2 as f64),
+            (max.0 as f64, max.1 as f64, max.2 as f64),
+        ))
+    }
+}
+
+impl From<TemplateBounds> for BoundingBox {
+    fn from(bounds: TemplateBounds) -> Self {
+        BoundingBox::from_corners(bounds.min_bound, bounds.max_bound)
+    }
+}
+
+/// Which bricks to save.
+///
+/// There's no way to save a single player's bricks: Omegga can only read the bricks in a
+/// region, not filter them by owner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveTarget {
+    /// Every brick on the server.
+    All,
+    /// The bricks within a region.
+    Region(BoundingBox),
+}
+
+/// The server's role setup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleSetup {
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    #[serde(rename = "defaultRole")]
+    pub default_role: Option<Role>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl RoleSetup {
+    /// Find a role by name, including the default role.
+    pub fn role(&self, name: &str) -> Option<&Role> {
+        self.roles
+            .iter()
+            .chain(self.default_role.as_ref())
+            .find(|role| role.name == name)
+    }
+}
+
+/// A role from the role setup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Role {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<RolePermission>,
+    pub color: Option<RoleColor>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Role {
+    /// The permissions the role sets, mapped to whether they're allowed.
+    /// Permissions left `Unchanged` are skipped.
+    pub fn permission_map(&self) -> HashMap<String, bool> {
+        self.permissions
+            .iter()
+            .filter(|permission| permission.state != "Unchanged")
+            .map(|permission| (permission.name.clone(), permission.is_allowed()))
+            .collect()
+    }
+}
+
+/// A permission set by a role.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RolePermission {
+    pub name: String,
+    /// One of `Allowed`, `Forbidden` or `Unchanged`.
+    #[serde(default)]
+    pub state: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl RolePermission {
+    /// Whether this permission is allowed by the role.
+    pub fn is_allowed(&self) -> bool {
+        self.state == "Allowed"
+    }
+}
+
+/// A role's color.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RoleColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    #[serde(default)]
+    pub a: u8,
+}
+
+/// Options for placing a loaded save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// The offset to load the save at, from its saved position.
+    pub offset: (i32, i32, i32),
+    /// Whether to skip announcing the load in chat.
+    pub quiet: bool,
+    /// Whether to remap colors outside the server's palette to the closest ones in it.
+    pub correct_palette: bool,
+    /// Whether to fix the sizes of bricks that can't be resized.
+    pub correct_custom: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            offset: (0, 0, 0),
+            quiet: true,
+            correct_palette: false,
+            correct_custom: false,
+        }
+    }
+}
+
+/// The server's status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStatus {
+    #[serde(rename = "serverName", default)]
+    pub server_name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub bricks: u64,
+    #[serde(default)]
+    pub components: u64,
+    /// How long the server has been up, in milliseconds.
+    #[serde(default)]
+    pub time: u64,
+    #[serde(default)]
+    pub players: Vec<PlayerStatus>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// A player from the server's status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerStatus {
+    pub name: String,
+    pub id: String,
+    #[serde(default)]
+    pub ping: u64,
+    /// How long the player has been connected, in milliseconds.
+    #[serde(default)]
+    pub time: u64,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub address: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// An environment preset, as read with [`Omegga::get_environment_data`](crate::Omegga::get_environment_data).
+///
+/// Only commonly tweaked settings are modeled. Everything else is kept in the `extra`
+/// maps, so a preset read, changed and loaded back keeps its other settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Environment {
+    #[serde(default)]
+    pub data: EnvironmentData,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// The settings in an environment preset.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EnvironmentData {
+    #[serde(default)]
+    pub groups: EnvironmentGroups,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// The groups of settings in an environment preset.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EnvironmentGroups {
+    #[serde(rename = "Sky", skip_serializing_if = "Option::is_none")]
+    pub sky: Option<SkySettings>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// The sky settings of an environment preset, covering the time of day, weather and fog.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkySettings {
+    /// The time of day, in hours from `0.0` to `24.0`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_of_day: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_change_speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sun_angle: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weather_intensity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rain_snow: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_coverage: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fog_density: Option<f64>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// A minigame running on the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Minigame {
+    /// The minigame's position in the server's list, used to control it.
+    pub index: i32,
+    #[serde(default)]
+    pub name: String,
+    /// The name of the ruleset the minigame was started from.
+    #[serde(default)]
+    pub ruleset: String,
+    #[serde(default)]
+    pub members: Vec<Player>,
+    #[serde(default)]
+    pub teams: Vec<MinigameTeam>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// A team in a minigame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinigameTeam {
+    #[serde(default)]
+    pub name: String,
+    /// The team's internal ID.
+    #[serde(default)]
+    pub team: String,
+    #[serde(default)]
+    pub members: Vec<Player>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// A ban from the server's ban list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanEntry {
+    /// The banned player's ID.
+    pub id: String,
+    /// The ID of the player who issued the ban.
+    pub banner_id: Option<String>,
+    pub reason: String,
+    /// When the ban was issued, as Brickadia writes it, like `2022.03.14-20.15.42`.
+    pub banned_at: Option<String>,
+    /// When the ban expires, in the same format, or `None` for a permanent ban.
+    pub expires_at: Option<String>,
+}
+
+/// A plugin, as Omegga reports it from `plugin.get`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Plugin {
     pub name: String,
+    /// The plugin's `doc.json`, or `None` if it has none.
+    #[serde(default)]
+    pub documentation: Option<PluginDocumentation>,
+    /// Whether the plugin is running, rather than only installed.
+    #[serde(default)]
+    pub loaded: bool,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// A plugin's `doc.json`, describing it and its config and commands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginDocumentation {
+    pub name: String,
+    #[serde(default)]
     pub description: String,
+    #[serde(default)]
     pub author: String,
+    #[serde(default)]
     pub config: HashMap<String, ConfigEntry>,
+    #[serde(default)]
     pub commands: Vec<Command>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 /// A config entry.
@@ -139,3 +654,31 @@ pub struct CommandArg {
     #[serde(default)]
     pub required: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_chat_markup() {
+        let message = ChatMessage::new()
+            .text("hi")
+            .color("ff0000")
+            .bold()
+            .text(" <b>&; ")
+            .raw("<emoji>smile</>")
+            .text("big")
+            .italics()
+            .size(20);
+
+        assert_eq!(
+            String::from(message),
+            "<color=\"ff0000\">**hi**</> &lt;b&gt;&amp;&scl; <emoji>smile</><size=\"20\">*big*</>"
+        );
+    }
+
+    #[test]
+    fn an_empty_chat_message_is_empty() {
+        assert_eq!(ChatMessage::new().bold().to_string(), "");
+    }
+}
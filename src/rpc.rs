@@ -1,16 +1,72 @@
 use std::convert::TryFrom;
 
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
 
 /// An RPC error, as defined in the RPC specification.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Error {
     code: i32,
     message: String,
+    /// Left out when `None`, since the specification makes it optional.
+    #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<Value>,
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rpc error {}: {}", self.code, self.message)?;
+        if let Some(data) = &self.data {
+            write!(f, " ({})", data)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn new(code: i32, message: impl Into<String>, data: Option<Value>) -> Self {
+        Error {
+            code,
+            message: message.into(),
+            data,
+        }
+    }
+
+    /// The message received was not valid JSON (`-32700`).
+    pub fn parse_error() -> Self {
+        Error::new(-32700, "Parse error", None)
+    }
+
+    /// The requested method does not exist (`-32601`).
+    pub fn method_not_found() -> Self {
+        Error::new(-32601, "Method not found", None)
+    }
+
+    /// The request's params were invalid (`-32602`).
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Error::new(-32602, message, None)
+    }
+
+    /// The request failed for an internal reason (`-32603`).
+    pub fn internal_error(message: impl Into<String>) -> Self {
+        Error::new(-32603, message, None)
+    }
+
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn data(&self) -> Option<&Value> {
+        self.data.as_ref()
+    }
+}
+
 /// An RPC request ID. Can be a string (`Str`) or an integer (`Int`).
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
 #[serde(untagged)]
@@ -19,41 +75,258 @@ pub enum RequestId {
     Int(i32),
 }
 
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestId::Str(id) => f.write_str(id),
+            RequestId::Int(id) => write!(f, "{}", id),
+        }
+    }
+}
+
 impl From<String> for RequestId {
     fn from(str: String) -> Self {
         RequestId::Str(str)
     }
 }
 
+impl From<&str> for RequestId {
+    fn from(str: &str) -> Self {
+        RequestId::Str(str.to_owned())
+    }
+}
+
 impl From<i32> for RequestId {
     fn from(i: i32) -> Self {
         RequestId::Int(i)
     }
 }
 
-/// An RPC message. One of [`Request`, `Response`, `Notification`].
+/// Structured message params, either positional (an array) or named (an object).
+///
+/// Some Omegga methods instead take a single bare value, like a player name, which
+/// is passed as a plain `Value`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
+pub enum Params {
+    Positional(Vec<Value>),
+    Named(Map<String, Value>),
+}
+
+impl Params {
+    pub fn positional(params: Vec<Value>) -> Self {
+        Params::Positional(params)
+    }
+
+    pub fn named(params: Map<String, Value>) -> Self {
+        Params::Named(params)
+    }
+}
+
+impl From<Params> for Value {
+    fn from(params: Params) -> Self {
+        match params {
+            Params::Positional(params) => Value::Array(params),
+            Params::Named(params) => Value::Object(params),
+        }
+    }
+}
+
+impl From<Params> for Option<Value> {
+    fn from(params: Params) -> Self {
+        Some(params.into())
+    }
+}
+
+/// The shape of params an Omegga method expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamsShape {
+    /// No params.
+    None,
+    /// A single bare value, like a player name.
+    Single,
+    /// An array of at least this many values.
+    Positional(usize),
+    /// An object with at least these keys.
+    Named(&'static [&'static str]),
+}
+
+/// The params shape of a known Omegga method, or `None` for a method not in the table.
+pub fn params_shape(method: &str) -> Option<ParamsShape> {
+    use ParamsShape::*;
+
+    Some(match method {
+        "getPlayers"
+        | "getAllPlayerPositions"
+        | "getRoleSetup"
+        | "getBanList"
+        | "getSaves"
+        | "getServerStatus"
+        | "getEnvironmentData"
+        | "getMinigames"
+        | "store.keys"
+        | "store.wipe" => None,
+
+        "log"
+        | "error"
+        | "info"
+        | "warn"
+        | "trace"
+        | "exec"
+        | "broadcast"
+        | "store.get"
+        | "store.delete"
+        | "getSavePath"
+        | "saveBricks"
+        | "readSaveData"
+        | "changeMap"
+        | "player.get"
+        | "player.getRoles"
+        | "player.getPermissions"
+        | "player.getNameColor"
+        | "player.getPosition"
+        | "player.getGhostBrick"
+        | "player.getPaint"
+        | "player.getTemplateBounds"
+        | "player.getTemplateBoundsData"
+        | "plugin.get" => Single,
+
+        "store.set" => Positional(2),
+        "plugin.emit" => Positional(2),
+
+        "whisper" | "middlePrint" => Named(&["target", "line"]),
+        "clearBricks" => Named(&["target"]),
+        "clearAllBricks" => Named(&[]),
+        "loadBricks" => Named(&["name"]),
+        "loadBricksOnPlayer" => Named(&["name", "player"]),
+        "loadSaveData" => Named(&["data"]),
+        "loadSaveDataOnPlayer" => Named(&["data", "player"]),
+        "player.loadDataAtGhostBrick" => Named(&["target", "data"]),
+        "writeSaveData" => Named(&["name", "data"]),
+
+        _ => return Option::None,
+    })
+}
+
+/// Check that params match the shape a known Omegga method expects, failing with
+/// [`WriteError::InvalidParams`](crate::WriteError::InvalidParams) if they don't.
+/// Methods not in the table are always accepted.
+pub fn validate_params(method: &str, params: Option<&Value>) -> Result<(), crate::WriteError> {
+    let expected = match params_shape(method) {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+
+    let valid = match (expected, params) {
+        (ParamsShape::None, None | Some(Value::Null)) => true,
+        (ParamsShape::Single, Some(value)) => !matches!(value, Value::Array(_) | Value::Object(_)),
+        (ParamsShape::Positional(len), Some(Value::Array(values))) => values.len() >= len,
+        (ParamsShape::Named(keys), Some(Value::Object(map))) => {
+            keys.iter().all(|key| map.contains_key(*key))
+        }
+        _ => false,
+    };
+
+    if !valid {
+        return Err(crate::WriteError::InvalidParams {
+            method: method.to_string(),
+            expected,
+        });
+    }
+    Ok(())
+}
+
+/// Serialize a value into message params, for passing a `Serialize` type to the
+/// `write_*` and `request` methods without building the JSON by hand.
+pub fn to_params(params: &impl Serialize) -> Result<Option<Value>, serde_json::Error> {
+    serde_json::to_value(params).map(Some)
+}
+
+/// The `jsonrpc` version of a message, defaulting to `"2.0"` when it's missing.
+fn default_version() -> String {
+    String::from("2.0")
+}
+
+/// Accept any `jsonrpc` version, keeping a non-string one as its JSON text.
+fn lenient_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(version) => version,
+        Value::Null => default_version(),
+        version => version.to_string(),
+    })
+}
+
+/// An RPC message. One of [`Request`, `Response`, `Notification`].
+///
+/// Messages are always written with `"jsonrpc": "2.0"`, but the field isn't checked on
+/// messages read from Omegga: a missing or `null` version is read as `"2.0"`, and any other
+/// is kept as is, since not every Omegga version sends it consistently.
+///
+/// `params` of `None` is left out of the serialized message, while `Some(Value::Null)`
+/// sends an explicit `"params": null` for methods that require it. A response with an
+/// `error` is written without a `result`, and one without an error always has a `result`,
+/// even if `null`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
 pub enum Message {
     Request {
+        #[serde(default = "default_version", deserialize_with = "lenient_version")]
         jsonrpc: String,
         id: RequestId,
         method: String,
         params: Option<Value>,
     },
     Response {
+        #[serde(default = "default_version", deserialize_with = "lenient_version")]
         jsonrpc: String,
         id: RequestId,
         result: Option<Value>,
         error: Option<Error>,
     },
     Notification {
+        #[serde(default = "default_version", deserialize_with = "lenient_version")]
         jsonrpc: String,
         method: String,
         params: Option<Value>,
     },
 }
 
+/// Written by hand, since which fields a response has depends on whether it failed.
+/// The version is always written as `"2.0"`, whatever a message was read with.
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("jsonrpc", "2.0")?;
+        match self {
+            Message::Request {
+                id, method, params, ..
+            } => {
+                map.serialize_entry("id", id)?;
+                map.serialize_entry("method", method)?;
+                if let Some(params) = params {
+                    map.serialize_entry("params", params)?;
+                }
+            }
+            Message::Response {
+                id, result, error, ..
+            } => {
+                map.serialize_entry("id", id)?;
+                match error {
+                    Some(error) => map.serialize_entry("error", error)?,
+                    None => map.serialize_entry("result", result)?,
+                }
+            }
+            Message::Notification { method, params, .. } => {
+                map.serialize_entry("method", method)?;
+                if let Some(params) = params {
+                    map.serialize_entry("params", params)?;
+                }
+            }
+        }
+        map.end()
+    }
+}
+
 impl Message {
     pub fn request(id: RequestId, method: String, params: Option<Value>) -> Self {
         Message::Request {
@@ -82,6 +355,41 @@ impl Message {
     }
 }
 
+/// A compact summary for logs, like `REQ #3 getPlayers`, `NOTIF chat` or `RESP #3 ok`.
+/// Serialize the message for the full JSON.
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Message::Request { id, method, .. } => write!(f, "REQ #{} {}", id, method),
+            Message::Response {
+                id, error: None, ..
+            } => write!(f, "RESP #{} ok", id),
+            Message::Response {
+                id,
+                error: Some(error),
+                ..
+            } => write!(f, "RESP #{} error {}", id, error.code),
+            Message::Notification { method, .. } => write!(f, "NOTIF {}", method),
+        }
+    }
+}
+
+/// The body of a response to write: a result, or an error, but never both.
+#[derive(Debug, Clone)]
+pub enum ResponseBody {
+    Ok(Option<Value>),
+    Err(Error),
+}
+
+impl From<Result<Option<Value>, Error>> for ResponseBody {
+    fn from(result: Result<Option<Value>, Error>) -> Self {
+        match result {
+            Ok(result) => ResponseBody::Ok(result),
+            Err(error) => ResponseBody::Err(error),
+        }
+    }
+}
+
 /// A struct that contains the same data as `Message::Response`.
 /// Used to save redundant `match`es against a `Message` that is
 /// known to be a `Message::Response`.
@@ -92,6 +400,34 @@ pub struct Response {
     pub error: Option<Error>,
 }
 
+impl Response {
+    /// Whether the response is a success, carrying no error.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The result of a successful response, or `None` for an error.
+    pub fn ok(self) -> Option<Option<Value>> {
+        match self.error {
+            Some(_) => None,
+            None => Some(self.result),
+        }
+    }
+
+    /// The error of a failed response, or `None` for a success.
+    pub fn err(self) -> Option<Error> {
+        self.error
+    }
+
+    /// Convert the response into its result, with an error as [`ResponseError::Rpc`](crate::ResponseError::Rpc).
+    pub fn into_result(self) -> Result<Option<Value>, crate::ResponseError> {
+        match self.error {
+            Some(error) => Err(crate::ResponseError::Rpc(error)),
+            None => Ok(self.result),
+        }
+    }
+}
+
 impl TryFrom<Message> for Response {
     type Error = ();
 
@@ -115,3 +451,112 @@ impl From<Response> for Message {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn request_ids_round_trip() {
+        for (id, json) in [
+            (RequestId::Int(3), "3"),
+            (RequestId::from("a-b"), r#""a-b""#),
+        ] {
+            assert_eq!(serde_json::to_string(&id).unwrap(), json);
+            assert_eq!(serde_json::from_str::<RequestId>(json).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn error_responses_have_no_result() {
+        let message = Message::response(
+            RequestId::Int(1),
+            None,
+            Some(Error::new(-32601, "no such method", None)),
+        );
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"no such method"}}"#
+        );
+    }
+
+    #[test]
+    fn error_data_is_written_when_present() {
+        let error = Error::new(-32000, "failed", Some(json!({"why": "x"})));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"code":-32000,"message":"failed","data":{"why":"x"}}"#
+        );
+    }
+
+    #[test]
+    fn params_are_left_out_only_when_none() {
+        for (message, expected) in [
+            (
+                Message::notification("m".into(), None),
+                r#"{"jsonrpc":"2.0","method":"m"}"#,
+            ),
+            (
+                Message::notification("m".into(), Some(Value::Null)),
+                r#"{"jsonrpc":"2.0","method":"m","params":null}"#,
+            ),
+            (
+                Message::request(RequestId::Int(1), "m".into(), None),
+                r#"{"jsonrpc":"2.0","id":1,"method":"m"}"#,
+            ),
+            (
+                Message::request(RequestId::Int(1), "m".into(), Some(Value::Null)),
+                r#"{"jsonrpc":"2.0","id":1,"method":"m","params":null}"#,
+            ),
+        ] {
+            assert_eq!(serde_json::to_string(&message).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn successful_responses_always_have_a_result() {
+        let empty = Message::response(RequestId::Int(1), None, None);
+        assert_eq!(
+            serde_json::to_string(&empty).unwrap(),
+            r#"{"jsonrpc":"2.0","id":1,"result":null}"#
+        );
+
+        let full = Message::response(RequestId::from("a"), Some(json!([1, 2])), None);
+        assert_eq!(
+            serde_json::to_string(&full).unwrap(),
+            r#"{"jsonrpc":"2.0","id":"a","result":[1,2]}"#
+        );
+    }
+
+    #[test]
+    fn reads_any_version() {
+        for (line, version) in [
+            (r#"{"method":"chat","params":["x","hi"]}"#, "2.0"),
+            (r#"{"jsonrpc":null,"method":"chat"}"#, "2.0"),
+            (r#"{"jsonrpc":"1.0","method":"chat"}"#, "1.0"),
+            (r#"{"jsonrpc":2,"method":"chat"}"#, "2"),
+        ] {
+            match serde_json::from_str(line).unwrap() {
+                Message::Notification { jsonrpc, .. } => assert_eq!(jsonrpc, version),
+                message => panic!("unexpected message {}", message),
+            }
+        }
+
+        assert!(matches!(
+            serde_json::from_str(r#"{"id":4,"result":[]}"#).unwrap(),
+            Message::Response { jsonrpc, .. } if jsonrpc == "2.0"
+        ));
+    }
+
+    #[test]
+    fn always_writes_version_two() {
+        let message: Message =
+            serde_json::from_str(r#"{"jsonrpc":"1.0","id":4,"method":"ping"}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"jsonrpc":"2.0","id":4,"method":"ping"}"#
+        );
+    }
+}
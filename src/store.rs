@@ -0,0 +1,57 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{rpc::Params, Omegga, ResponseError, WriteError};
+
+/// A handle to the plugin's persistent key-value store. Get one with [`Omegga::store`](Omegga::store).
+pub struct Store<'a> {
+    omegga: &'a Omegga,
+}
+
+impl<'a> Store<'a> {
+    pub(crate) fn new(omegga: &'a Omegga) -> Self {
+        Self { omegga }
+    }
+
+    /// Gets an object from the store, deserialized into `T`.
+    /// Returns `Ok(None)` if the key is absent.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, ResponseError> {
+        match self
+            .omegga
+            .request("store.get", Some(Value::String(key.into())))
+            .await?
+        {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => Ok(Some(serde_json::from_value::<T>(value)?)),
+        }
+    }
+
+    /// Sets an object in the store.
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<(), WriteError> {
+        let value = serde_json::to_value(value)?;
+        self.omegga.write_notification(
+            "store.set",
+            Params::positional(vec![key.into(), value]).into(),
+        );
+        Ok(())
+    }
+
+    /// Deletes an object from the store.
+    pub fn delete(&self, key: &str) {
+        self.omegga
+            .write_notification("store.delete", Some(Value::String(key.into())));
+    }
+
+    /// Wipes the store.
+    pub fn wipe(&self) {
+        self.omegga.write_notification("store.wipe", None);
+    }
+
+    /// Gets a list of keys in the store.
+    pub async fn keys(&self) -> Result<Vec<String>, ResponseError> {
+        match self.omegga.request("store.keys", None).await? {
+            Some(keys) => Ok(serde_json::from_value::<Vec<String>>(keys)?),
+            None => Ok(vec![]),
+        }
+    }
+}